@@ -0,0 +1,78 @@
+// startup time of the shell: how long `exit` on stdin takes from spawn to exit, with the
+// normal $PATH and with one padded out with thousands of executables. Run with
+// `cargo bench --bench startup`
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 20;
+const PADDING_DIRS: usize = 20;
+const PADDING_FILES: usize = 500;
+
+fn start_once(path: &str, scratch: &Path) -> Duration {
+    let started = Instant::now();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_shelly"))
+        .env("PATH", path)
+        .env("HISTFILE", "")
+        .env("XDG_DATA_HOME", scratch)
+        .env("XDG_STATE_HOME", scratch)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("could not start shelly");
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(b"exit\n");
+    }
+    let _ = child.wait();
+    started.elapsed()
+}
+
+fn report(label: &str, path: &str, scratch: &Path) {
+    let mut times: Vec<Duration> = (0..RUNS).map(|_| start_once(path, scratch)).collect();
+    times.sort();
+    println!(
+        "{:<24} median {:>8.2} ms   min {:>8.2} ms   max {:>8.2} ms",
+        label,
+        times[RUNS / 2].as_secs_f64() * 1000.0,
+        times[0].as_secs_f64() * 1000.0,
+        times[RUNS - 1].as_secs_f64() * 1000.0,
+    );
+}
+
+// directories full of empty executables, ahead of the normal $PATH
+fn padded_path(scratch: &Path, path: &str) -> String {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for d in 0..PADDING_DIRS {
+        let dir = scratch.join(format!("bin{}", d));
+        fs::create_dir_all(&dir).expect("could not create padding directory");
+        for f in 0..PADDING_FILES {
+            let file = dir.join(format!("tool-{}-{}", d, f));
+            if !file.exists() {
+                fs::write(&file, "").expect("could not create padding file");
+                fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).expect("could not chmod padding file");
+            }
+        }
+        dirs.push(dir);
+    }
+    dirs.extend(env::split_paths(path));
+    env::join_paths(dirs).expect("bad PATH").to_string_lossy().into_owned()
+}
+
+fn main() {
+    let scratch = env::temp_dir().join(format!("shelly-startup-bench-{}", std::process::id()));
+    fs::create_dir_all(&scratch).expect("could not create scratch directory");
+    let path = env::var("PATH").unwrap_or_default();
+
+    report("default PATH", &path, &scratch);
+    report(
+        &format!("+{} commands on PATH", PADDING_DIRS * PADDING_FILES),
+        &padded_path(&scratch, &path),
+        &scratch,
+    );
+    let _ = fs::remove_dir_all(&scratch);
+}
@@ -0,0 +1,364 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use pathsearch::find_executable_in_path;
+
+use crate::handler::{builtin_output_handler, SHELL_COMMANDS};
+
+// the executables of one $PATH directory, rescanned when its mtime moves
+struct PathDir {
+    dir: PathBuf,
+    scanned: bool,
+    modified: Option<SystemTime>,
+    names: Vec<String>,
+}
+
+// commands `hash` remembers, with how often they were looked up
+struct Remembered {
+    name: String,
+    path: PathBuf,
+    hits: u32,
+}
+
+struct CommandCache {
+    path: Option<OsString>,
+    dirs: Vec<PathDir>,
+    remembered: Vec<Remembered>,
+    // when the directory mtimes were last looked at
+    checked: Option<Instant>,
+    // the builtins and every name in `dirs`, sorted, built again after a directory changes
+    names: Option<Vec<String>>,
+}
+
+static COMMAND_CACHE: Mutex<CommandCache> = Mutex::new(CommandCache {
+    path: None,
+    dirs: Vec::new(),
+    remembered: Vec::new(),
+    checked: None,
+    names: None,
+});
+
+// highlighting looks commands up on every key, the directories are only stat'ed this often.
+// A miss still goes to the disk, so a new command is never reported missing
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// set while a background thread reads directories, so only one does at a time
+static SCANNING: AtomicBool = AtomicBool::new(false);
+
+fn modified(dir: &Path) -> Option<SystemTime> {
+    fs::metadata(dir).and_then(|metadata| metadata.modified()).ok()
+}
+
+// executable files of `dir`, sorted, skipping entries that cannot be read instead of
+// giving up on the directory. The file type comes with the directory listing, so only
+// files and symlinks cost a stat, and only one
+fn scan_dir(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = Vec::new();
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let metadata = if file_type.is_dir() {
+            continue;
+        } else if file_type.is_symlink() {
+            // symlinks are judged by what they point to
+            fs::metadata(entry.path())
+        } else {
+            entry.metadata()
+        };
+        let Ok(metadata) = metadata else {
+            continue;
+        };
+        if metadata.is_file()
+            && metadata.permissions().mode() & 0o111 != 0
+            && let Ok(name) = entry.file_name().into_string()
+        {
+            names.push(name);
+        }
+    }
+    names.sort();
+    names
+}
+
+// reads the directories one at a time and stores each as soon as it is done, so
+// completion sees commands while the rest are still loading
+fn scan_in_background(dirs: Vec<PathBuf>) {
+    let spawned = thread::Builder::new().name("path-scan".to_string()).spawn(move || {
+        for dir in dirs {
+            let modified = modified(&dir);
+            let names = scan_dir(&dir);
+            let Ok(mut cache) = COMMAND_CACHE.lock() else {
+                break;
+            };
+            if let Some(known) = cache.dirs.iter_mut().find(|known| known.dir == dir) {
+                known.names = names;
+                known.modified = modified;
+                known.scanned = true;
+            }
+            cache.names = None;
+        }
+        SCANNING.store(false, Ordering::Release);
+    });
+    if spawned.is_err() {
+        SCANNING.store(false, Ordering::Release);
+    }
+}
+
+// starts reading $PATH without holding up the first prompt
+pub fn scan_path() {
+    if let Ok(mut cache) = COMMAND_CACHE.lock() {
+        cache.refresh();
+    }
+}
+
+impl CommandCache {
+    // brings the cache in line with $PATH. Directories that are new or whose mtime moved
+    // are read again by a background thread, until then lookups see what was there before.
+    // A changed $PATH is noticed at once, mtimes at most every CHECK_INTERVAL
+    fn refresh(&mut self) {
+        let path = env::var_os("PATH");
+        if path != self.path {
+            let mut old = std::mem::take(&mut self.dirs);
+            self.dirs = env::split_paths(path.as_deref().unwrap_or_default())
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .map(|dir| match old.iter().position(|known| known.dir == dir) {
+                    Some(i) => old.swap_remove(i),
+                    None => PathDir {
+                        dir,
+                        scanned: false,
+                        modified: None,
+                        names: Vec::new(),
+                    },
+                })
+                .collect();
+            // a directory listed twice only counts where it first appears
+            let mut seen: Vec<PathBuf> = Vec::new();
+            self.dirs.retain(|dir| {
+                let first = !seen.contains(&dir.dir);
+                seen.push(dir.dir.clone());
+                first
+            });
+            self.path = path;
+            self.remembered.clear();
+            self.checked = None;
+            self.names = None;
+        }
+        if self.checked.is_some_and(|checked| checked.elapsed() < CHECK_INTERVAL) {
+            return;
+        }
+        self.checked = Some(Instant::now());
+
+        let stale: Vec<PathBuf> = self
+            .dirs
+            .iter()
+            .filter(|dir| !dir.scanned || modified(&dir.dir) != dir.modified)
+            .map(|dir| dir.dir.clone())
+            .collect();
+        if !stale.is_empty() && !SCANNING.swap(true, Ordering::Acquire) {
+            scan_in_background(stale);
+        }
+        // a remembered path that went away is looked up again next time
+        self.remembered.retain(|remembered| remembered.path.is_file());
+    }
+
+    // the first $PATH directory that has `name`. A miss is checked on disk, since the scan
+    // may not have got there yet and a chmod does not touch the directory mtime, and the
+    // directory that has it is read again
+    fn find(&mut self, name: &str) -> Option<PathBuf> {
+        for dir in &self.dirs {
+            // an earlier directory that is not read yet could still have it
+            if !dir.scanned {
+                break;
+            }
+            if dir.names.binary_search_by(|known| known.as_str().cmp(name)).is_ok() {
+                return Some(dir.dir.join(name));
+            }
+        }
+        let path = find_executable_in_path(name)?;
+        if let Some(dir) = self.dirs.iter_mut().find(|dir| Some(dir.dir.as_path()) == path.parent()) {
+            dir.scanned = false;
+        }
+        Some(path)
+    }
+
+    fn names(&mut self) -> &[String] {
+        self.names.get_or_insert_with(|| {
+            let mut names: Vec<String> = SHELL_COMMANDS.iter().map(|name| name.to_string()).collect();
+            names.extend(self.dirs.iter().flat_map(|dir| dir.names.iter().cloned()));
+            names.sort();
+            names.dedup();
+            names
+        })
+    }
+
+    fn remember(&mut self, name: &str, path: PathBuf) {
+        match self.remembered.iter_mut().find(|remembered| remembered.name == name) {
+            Some(remembered) => {
+                remembered.path = path;
+                remembered.hits += 1;
+            }
+            None => self.remembered.push(Remembered {
+                name: name.to_string(),
+                path,
+                hits: 1,
+            }),
+        }
+    }
+}
+
+// every builtin and executable name on $PATH, sorted and without duplicates
+pub fn command_names() -> Vec<String> {
+    let Ok(mut cache) = COMMAND_CACHE.lock() else {
+        return Vec::new();
+    };
+    cache.refresh();
+    cache.names().to_vec()
+}
+
+// the first command name that starts with `prefix` and goes on past it, for the hinter,
+// which asks on every key
+pub fn command_extending(prefix: &str) -> Option<String> {
+    let mut cache = COMMAND_CACHE.lock().ok()?;
+    cache.refresh();
+    let names = cache.names();
+    let start = names.partition_point(|name| name.as_str() < prefix);
+    names[start..]
+        .iter()
+        .take_while(|name| name.starts_with(prefix))
+        .find(|name| name.len() > prefix.len())
+        .cloned()
+}
+
+// where `name` runs from, in $PATH order. Names with a `/` are paths already
+pub fn find_command(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return find_executable_in_path(name);
+    }
+    let mut cache = COMMAND_CACHE.lock().ok()?;
+    cache.refresh();
+    cache.find(name)
+}
+
+// like find_command, also counted in the table `hash` prints
+pub fn hash_command(name: &str) -> Option<PathBuf> {
+    let path = find_command(name)?;
+    if !name.contains('/')
+        && let Ok(mut cache) = COMMAND_CACHE.lock()
+    {
+        cache.remember(name, path.clone());
+    }
+    Some(path)
+}
+
+// drops `name` after it failed to start, so the next lookup goes to the disk again and its
+// directory is read again
+pub fn forget_command(name: &str) {
+    let Ok(mut cache) = COMMAND_CACHE.lock() else {
+        return;
+    };
+    cache.remembered.retain(|remembered| remembered.name != name);
+    for dir in &mut cache.dirs {
+        if let Ok(i) = dir.names.binary_search_by(|known| known.as_str().cmp(name)) {
+            dir.names.remove(i);
+            dir.scanned = false;
+        }
+    }
+    cache.names = None;
+}
+
+// `hash` lists remembered commands, `hash name` looks them up, -r forgets all of them,
+// -d the ones named, and -t prints where they are
+pub fn hash_output(args: &[String], command: &str) -> Result<String, String> {
+    let command = command.trim();
+    let mut reset = false;
+    let mut delete = false;
+    let mut print = false;
+    let mut names: Vec<&String> = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('-').filter(|flags| !flags.is_empty() && names.is_empty()) {
+            Some(flags) => {
+                for flag in flags.chars() {
+                    match flag {
+                        'r' => reset = true,
+                        'd' => delete = true,
+                        't' => print = true,
+                        _ => return Err(format!("{}: -{}: invalid option", command, flag)),
+                    }
+                }
+            }
+            None => names.push(arg),
+        }
+    }
+
+    let Ok(mut cache) = COMMAND_CACHE.lock() else {
+        return Err(format!("{}: command cache unavailable", command));
+    };
+    if reset {
+        // also has every directory read again
+        cache.remembered.clear();
+        cache.dirs.clear();
+        cache.path = None;
+        cache.names = None;
+    }
+    cache.refresh();
+
+    if delete {
+        for name in &names {
+            if !cache.remembered.iter().any(|remembered| remembered.name == **name) {
+                return Err(format!("{}: {}: not found", command, name));
+            }
+            cache.remembered.retain(|remembered| remembered.name != **name);
+        }
+        return Ok(String::new());
+    }
+
+    if names.is_empty() {
+        if print {
+            return Err(format!("{}: -t: option requires an argument", command));
+        }
+        if cache.remembered.is_empty() {
+            return if reset { Ok(String::new()) } else { Ok(format!("{}: hash table empty", command)) };
+        }
+        let mut lines = vec!["hits\tcommand".to_string()];
+        lines.extend(
+            cache
+                .remembered
+                .iter()
+                .map(|remembered| format!("{:4}\t{}", remembered.hits, remembered.path.display())),
+        );
+        return Ok(lines.join("\n"));
+    }
+
+    let mut lines: Vec<String> = Vec::new();
+    for name in names {
+        let Some(path) = cache.find(name) else {
+            return Err(format!("{}: {}: not found", command, name));
+        };
+        if print {
+            lines.push(path.display().to_string());
+        }
+        // bash starts a command it only looked up at zero hits
+        if !cache.remembered.iter().any(|remembered| remembered.name == *name) {
+            cache.remembered.push(Remembered {
+                name: name.clone(),
+                path,
+                hits: 0,
+            });
+        }
+    }
+    Ok(lines.join("\n"))
+}
+
+pub fn hash_handler(args: &[String], command: &str, redirect: bool, redirects: Vec<(String, String)>) {
+    builtin_output_handler(hash_output(args, command), redirect, redirects);
+}
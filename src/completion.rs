@@ -0,0 +1,281 @@
+use std::fs;
+use std::path::PathBuf;
+
+use is_executable::IsExecutable;
+use rustyline::completion::Pair;
+
+use crate::input_parser::{expand_tilde, tokenize, Token, TokenKind};
+
+// the word under the cursor, from the tokens input_parser reads
+pub struct WordContext {
+    // byte offset in the line where the word, quotes included, starts
+    pub start: usize,
+    // the word with its quotes and escapes removed
+    pub word: String,
+    // the quote still open at the cursor, if any
+    pub quote: Option<char>,
+    // first word of a pipeline stage, not after a redirect
+    pub command_position: bool,
+    // the finished words of the current pipeline stage
+    pub words_before: Vec<String>,
+}
+
+pub fn word_at(line: &str, pos: usize) -> WordContext {
+    let before = &line[..pos];
+    let tokens = tokenize(before);
+    // the cursor is in a word when one runs up to it, otherwise a new one starts there
+    let (finished, current) = match tokens.tokens.split_last() {
+        Some((last, finished)) if last.kind == TokenKind::Word && last.end == pos => (finished, Some(last)),
+        _ => (tokens.tokens.as_slice(), None),
+    };
+
+    let mut words_before: Vec<String> = Vec::new();
+    let mut previous: Option<&Token> = None;
+    for token in finished.iter().filter(|token| token.kind != TokenKind::Comment) {
+        // an operator or a newline starts the next command
+        if token.kind == TokenKind::Operator
+            || previous.is_some_and(|previous| before[previous.end..token.start].contains('\n'))
+        {
+            words_before.clear();
+        }
+        let is_target = previous.is_some_and(|previous| previous.kind == TokenKind::Redirect);
+        // a backslash newline leaves an empty word behind
+        let is_empty = token.text.is_empty() && token.quoted.is_empty();
+        if token.kind == TokenKind::Word && !is_target && !is_empty {
+            words_before.push(token.text.clone());
+        }
+        previous = Some(token);
+    }
+
+    match current {
+        Some(token) => WordContext {
+            start: token.start,
+            word: token.text.clone(),
+            quote: tokens.open_quote,
+            command_position: token.command_position,
+            words_before,
+        },
+        None => WordContext {
+            start: pos,
+            word: String::new(),
+            quote: None,
+            command_position: tokens.expects_command,
+            words_before,
+        },
+    }
+}
+
+// characters a bare word has to escape to mean themselves
+fn needs_escape(c: char) -> bool {
+    c.is_whitespace()
+        || matches!(
+            c,
+            '\'' | '"' | '\\' | '|' | '<' | '>' | '&' | ';' | '(' | ')' | '$' | '`' | '*' | '?' | '[' | ']' | '#' | '!' | '{' | '}'
+        )
+}
+
+// writes `text` back in the quoting style the word was started in
+pub fn quote_word(text: &str, quote: Option<char>) -> String {
+    let mut quoted = String::new();
+    match quote {
+        Some('\'') => {
+            quoted.push('\'');
+            quoted.push_str(&text.replace('\'', "'\\''"));
+        }
+        Some(_) => {
+            quoted.push('"');
+            for c in text.chars() {
+                if matches!(c, '\\' | '"' | '$' | '`') {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+        }
+        None => {
+            for (i, c) in text.chars().enumerate() {
+                // a leading `~` stays bare so it still expands
+                if needs_escape(c) && !(i == 0 && c == '~') {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+        }
+    }
+    quoted
+}
+
+// which entries a path completion offers besides directories
+#[derive(Clone, Copy)]
+pub enum PathFilter {
+    All,
+    Executables,
+    Directories,
+}
+
+// a path match: the word as it should read, the entry name and whether it is a directory
+pub struct PathMatch {
+    pub path: String,
+    pub name: String,
+    pub is_dir: bool,
+}
+
+// entries of the directory `word` points into whose names start with the rest of it
+pub fn matching_paths(word: &str, filter: PathFilter) -> Vec<PathMatch> {
+    let (directory, prefix) = match word.rfind('/') {
+        Some(slash) => word.split_at(slash + 1),
+        None => ("", word),
+    };
+
+    let search_dir = if directory.is_empty() {
+        PathBuf::from(".")
+    } else if let Some(rest) = directory.strip_prefix('~') {
+        let (user, path) = rest.split_once('/').unwrap_or((rest, ""));
+        match expand_tilde(user) {
+            Some(home) => PathBuf::from(home).join(path),
+            None => PathBuf::from(directory),
+        }
+    } else {
+        PathBuf::from(directory)
+    };
+
+    let Ok(entries) = fs::read_dir(&search_dir) else {
+        return Vec::new();
+    };
+    let mut matches: Vec<PathMatch> = Vec::new();
+    for entry in entries.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        // hidden files only when asked for
+        if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            continue;
+        }
+        let path = entry.path();
+        let is_dir = path.is_dir();
+        let wanted = match filter {
+            PathFilter::All => true,
+            PathFilter::Executables => is_dir || path.is_executable(),
+            PathFilter::Directories => is_dir,
+        };
+        if !wanted {
+            continue;
+        }
+
+        matches.push(PathMatch {
+            path: format!("{}{}", directory, name),
+            name,
+            is_dir,
+        });
+    }
+    matches.sort_by(|a, b| a.name.cmp(&b.name));
+    matches
+}
+
+// files and directories matching the word, directories end in `/` and everything else
+// in a space, closing the quote first if one is open
+pub fn path_candidates(context: &WordContext, filter: PathFilter) -> Vec<Pair> {
+    matching_paths(&context.word, filter)
+        .iter()
+        .map(|found| word_candidate(&found.path, &found.name, found.is_dir, context.quote, true))
+        .collect()
+}
+
+// `text` quoted for the line, a directory ends in `/` and anything else closes the quote
+// and gets a space unless `add_space` is off
+pub fn word_candidate(text: &str, display: &str, is_dir: bool, quote: Option<char>, add_space: bool) -> Pair {
+    let mut replacement = quote_word(text, quote);
+    if is_dir {
+        replacement.push('/');
+        return Pair {
+            display: format!("{}/", display),
+            replacement,
+        };
+    }
+    if add_space {
+        if let Some(quote) = quote {
+            replacement.push(quote);
+        }
+        replacement.push(' ');
+    }
+    Pair {
+        display: display.to_string(),
+        replacement,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the word, its start and whether it is a command, with the cursor at the end
+    fn at_end(line: &str) -> (String, usize, bool) {
+        let context = word_at(line, line.len());
+        (context.word, context.start, context.command_position)
+    }
+
+    #[test]
+    fn words_follow_the_parser() {
+        assert_eq!(at_end(""), (String::new(), 0, true));
+        assert_eq!(at_end("gi"), ("gi".to_string(), 0, true));
+        assert_eq!(at_end("git ch"), ("ch".to_string(), 4, false));
+        assert_eq!(at_end("ls | gr"), ("gr".to_string(), 5, true));
+        assert_eq!(at_end("ls |gr"), ("gr".to_string(), 4, true));
+        assert_eq!(at_end("cat <in"), ("in".to_string(), 5, false));
+        assert_eq!(at_end("ls 2>er"), ("er".to_string(), 5, false));
+        assert_eq!(at_end("ls ~/My\\ Do"), ("~/My Do".to_string(), 3, false));
+        assert_eq!(at_end("if gi"), ("gi".to_string(), 3, true));
+        // the operators the executor refuses still end the word
+        assert_eq!(at_end("true && fa"), ("fa".to_string(), 8, true));
+        assert_eq!(at_end("echo a;ec"), ("ec".to_string(), 7, true));
+        assert_eq!(at_end("echo $HO"), ("$HO".to_string(), 5, false));
+        // nothing to complete after a comment starts
+        assert_eq!(at_end("ls # no"), (String::new(), 7, false));
+    }
+
+    #[test]
+    fn open_quotes_are_reported() {
+        let context = word_at("cat \"My Do", 10);
+        assert_eq!((context.word.as_str(), context.start, context.quote), ("My Do", 4, Some('"')));
+        let context = word_at("cat 'a\\b", 8);
+        assert_eq!((context.word.as_str(), context.quote), ("a\\b", Some('\'')));
+        let context = word_at("cat \"a\" b", 9);
+        assert_eq!(context.quote, None);
+    }
+
+    #[test]
+    fn cursor_in_the_middle() {
+        let context = word_at("git checkout main", 6);
+        assert_eq!((context.word.as_str(), context.start), ("ch", 4));
+        assert_eq!(context.words_before, ["git"]);
+    }
+
+    #[test]
+    fn words_before_the_cursor() {
+        let context = word_at("ls -l | git -C dir > out log ", 29);
+        assert_eq!(context.words_before, ["git", "-C", "dir", "log"]);
+        assert!(!context.command_position);
+        let context = word_at("echo one \\\ntwo ", 15);
+        assert_eq!(context.words_before, ["echo", "one", "two"]);
+    }
+
+    #[test]
+    fn quoting_round_trips() {
+        let cases = [
+            ("My Documents", None, "My\\ Documents"),
+            ("a&b;c#d$e", None, "a\\&b\\;c\\#d\\$e"),
+            ("~/a b", None, "~/a\\ b"),
+            ("it's", Some('\''), "'it'\\''s"),
+            ("say \"$HOME\"", Some('"'), "\"say \\\"\\$HOME\\\""),
+        ];
+        for (text, quote, expected) in cases {
+            let quoted = quote_word(text, quote);
+            assert_eq!(quoted, expected);
+            // a word closed after the replacement reads back as the text
+            let closed = match quote {
+                Some(quote) => format!("{}{}", quoted, quote),
+                None => quoted,
+            };
+            assert_eq!(word_at(&closed, closed.len()).word, text);
+        }
+    }
+}
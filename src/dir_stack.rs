@@ -0,0 +1,312 @@
+use std::env;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::handler::{builtin_output_handler, change_directory, describe_io_error, logical_pwd};
+
+// entries below the top of the stack, the top itself is always $PWD
+static DIR_STACK: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn saved_entries() -> Vec<String> {
+    DIR_STACK.lock().map(|stack| stack.clone()).unwrap_or_default()
+}
+
+fn save_entries(entries: Vec<String>) {
+    if let Ok(mut stack) = DIR_STACK.lock() {
+        *stack = entries;
+    }
+}
+
+// the whole stack as `dirs` shows it, current directory first
+pub fn dir_stack_entries() -> Vec<String> {
+    let mut entries = vec![logical_pwd().unwrap_or_default()];
+    entries.extend(saved_entries());
+    entries
+}
+
+// `+N` counts from the top (the left of `dirs`), `-N` from the bottom
+fn stack_index(arg: &str, len: usize) -> Option<usize> {
+    let (from_top, digits) = if let Some(digits) = arg.strip_prefix('+') {
+        (true, digits)
+    } else if let Some(digits) = arg.strip_prefix('-') {
+        (false, digits)
+    } else {
+        (true, arg)
+    };
+    let n: usize = digits.parse().ok()?;
+    if n >= len {
+        return None;
+    }
+    Some(if from_top { n } else { len - 1 - n })
+}
+
+fn is_stack_index(arg: &str) -> bool {
+    let digits = arg.strip_prefix(['+', '-']).unwrap_or("");
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+// `N`, `+N` or `-N`, anything else after a `~` is a user name
+pub fn is_stack_prefix(prefix: &str) -> bool {
+    let digits = prefix.strip_prefix(['+', '-']).unwrap_or(prefix);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+// `~N`, `~+N` and `~-N` tilde prefixes
+pub fn stack_entry(prefix: &str) -> Option<String> {
+    if !is_stack_prefix(prefix) {
+        return None;
+    }
+    let entries = dir_stack_entries();
+    let index = stack_index(prefix, entries.len())?;
+    entries.get(index).cloned()
+}
+
+fn tilde_form(entry: &str) -> String {
+    if let Ok(home) = env::var("HOME")
+        && !home.is_empty()
+        && let Some(rest) = entry.strip_prefix(&home)
+        && (rest.is_empty() || rest.starts_with('/'))
+    {
+        return format!("~{}", rest);
+    }
+    entry.to_string()
+}
+
+pub fn dirs_output(args: &[String], command: &str) -> Result<String, String> {
+    let mut long = false;
+    let mut per_line = false;
+    let mut numbered = false;
+    let mut index: Option<&str> = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "-c" => {
+                save_entries(Vec::new());
+                return Ok(String::new());
+            }
+            "-l" => long = true,
+            "-p" => per_line = true,
+            "-v" => {
+                per_line = true;
+                numbered = true;
+            }
+            _ if is_stack_index(arg) => index = Some(arg),
+            _ => return Err(format!("{}: {}: invalid option", command.trim(), arg)),
+        }
+    }
+
+    let entries = dir_stack_entries();
+    let display = |entry: &String| if long { entry.clone() } else { tilde_form(entry) };
+
+    if let Some(index) = index {
+        return match stack_index(index, entries.len()) {
+            Some(n) => Ok(display(&entries[n])),
+            None => Err(format!("{}: {}: directory stack index out of range", command.trim(), index)),
+        };
+    }
+
+    let lines: Vec<String> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            if numbered {
+                format!("{:2}  {}", i, display(entry))
+            } else {
+                display(entry)
+            }
+        })
+        .collect();
+    Ok(lines.join(if per_line { "\n" } else { " " }))
+}
+
+pub fn dirs_handler(
+    args: &[String],
+    command: &str,
+    redirect: bool,
+    redirects: Vec<(String, String)>,
+) {
+    builtin_output_handler(dirs_output(args, command), redirect, redirects);
+}
+
+fn enter(directory: &str, command: &str) -> Result<(), String> {
+    change_directory(Path::new(directory), false)
+        .map(|_| ())
+        .map_err(|e| format!("{}: {}: {}", command.trim(), directory, describe_io_error(&e)))
+}
+
+// brings entry `n` to the top, the entries above it go round to the bottom
+fn rotate_stack(entries: &mut [String], n: usize, no_cd: bool) {
+    if no_cd {
+        // the top is always $PWD, so only the entries under it move
+        entries[1..].rotate_left(n.saturating_sub(1));
+    } else {
+        entries.rotate_left(n);
+    }
+}
+
+fn pushd_output(args: &[String], command: &str) -> Result<String, String> {
+    let mut no_cd = false;
+    let mut operand: Option<&str> = None;
+    for arg in args {
+        match arg.as_str() {
+            "-n" => no_cd = true,
+            _ if operand.is_some() => {
+                return Err(format!("{}: too many arguments", command.trim()));
+            }
+            _ => operand = Some(arg),
+        }
+    }
+
+    let mut entries = dir_stack_entries();
+    match operand {
+        None => {
+            if entries.len() < 2 {
+                return Err(format!("{}: no other directory", command.trim()));
+            }
+            if !no_cd {
+                entries.swap(0, 1);
+                enter(&entries[0], command)?;
+            }
+        }
+        Some(index) if is_stack_index(index) => {
+            let Some(n) = stack_index(index, entries.len()) else {
+                return Err(format!("{}: {}: directory stack index out of range", command.trim(), index));
+            };
+            rotate_stack(&mut entries, n, no_cd);
+            if !no_cd {
+                enter(&entries[0], command)?;
+            }
+        }
+        Some(directory) => {
+            if no_cd {
+                entries.insert(1, directory.to_string());
+            } else {
+                enter(directory, command)?;
+                entries.insert(0, logical_pwd().unwrap_or_default());
+            }
+        }
+    }
+
+    save_entries(entries[1..].to_vec());
+    dirs_output(&[], "dirs")
+}
+
+fn popd_output(args: &[String], command: &str) -> Result<String, String> {
+    let mut no_cd = false;
+    let mut index: Option<&str> = None;
+    for arg in args {
+        match arg.as_str() {
+            "-n" => no_cd = true,
+            _ if is_stack_index(arg) && index.is_none() => index = Some(arg),
+            _ => return Err(format!("{}: {}: invalid argument", command.trim(), arg)),
+        }
+    }
+
+    let mut entries = dir_stack_entries();
+    if entries.len() < 2 {
+        return Err(format!("{}: directory stack empty", command.trim()));
+    }
+
+    let n = match index {
+        Some(index) => match stack_index(index, entries.len()) {
+            Some(n) => n,
+            None => {
+                return Err(format!("{}: {}: directory stack index out of range", command.trim(), index));
+            }
+        },
+        // with -n the top stays put and the entry under it goes
+        None if no_cd => 1,
+        None => 0,
+    };
+
+    entries.remove(n);
+    if n == 0 {
+        enter(&entries[0], command)?;
+    }
+
+    save_entries(entries[1..].to_vec());
+    dirs_output(&[], "dirs")
+}
+
+pub fn pushd_handler(
+    args: &[String],
+    command: &str,
+    redirect: bool,
+    redirects: Vec<(String, String)>,
+) {
+    builtin_output_handler(pushd_output(args, command), redirect, redirects);
+}
+
+pub fn popd_handler(
+    args: &[String],
+    command: &str,
+    redirect: bool,
+    redirects: Vec<(String, String)>,
+) {
+    builtin_output_handler(popd_output(args, command), redirect, redirects);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stack_indexes() {
+        let cases = [
+            ("+0", 3, Some(0)),
+            ("+2", 3, Some(2)),
+            ("2", 3, Some(2)),
+            ("-0", 3, Some(2)),
+            ("-2", 3, Some(0)),
+            ("+3", 3, None),
+            ("-3", 3, None),
+            ("+1", 1, None),
+            ("+x", 3, None),
+            ("+", 3, None),
+        ];
+        for (arg, len, expected) in cases {
+            assert_eq!(stack_index(arg, len), expected, "{:?} {}", arg, len);
+        }
+    }
+
+    #[test]
+    fn index_arguments_and_tilde_prefixes() {
+        // (arg, pushd index, tilde prefix)
+        let cases = [
+            ("+1", true, true),
+            ("-12", true, true),
+            ("1", false, true),
+            ("+", false, false),
+            ("-", false, false),
+            ("+1x", false, false),
+            ("-n", false, false),
+            ("root", false, false),
+            ("", false, false),
+        ];
+        for (arg, index, prefix) in cases {
+            assert_eq!(is_stack_index(arg), index, "{:?}", arg);
+            assert_eq!(is_stack_prefix(arg), prefix, "{:?}", arg);
+        }
+    }
+
+    #[test]
+    fn rotations() {
+        let cases = [
+            ("+1", false, "b c d a"),
+            ("+2", false, "c d a b"),
+            ("-0", false, "d a b c"),
+            ("+0", false, "a b c d"),
+            // with -n the top stays where it is
+            ("+2", true, "a c d b"),
+            ("-0", true, "a d b c"),
+            ("+1", true, "a b c d"),
+            ("+0", true, "a b c d"),
+        ];
+        for (arg, no_cd, expected) in cases {
+            let mut entries: Vec<String> = ["a", "b", "c", "d"].map(String::from).to_vec();
+            let n = stack_index(arg, entries.len()).unwrap();
+            rotate_stack(&mut entries, n, no_cd);
+            assert_eq!(entries.join(" "), expected, "{:?} {}", arg, no_cd);
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::env;
+use std::ffi::{CString, OsString};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::fd::FromRawFd;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::PathBuf;
+use std::process::Command;
+
+use rustyline::Editor;
+
+use crate::MyHelper;
+use crate::handler::builtin_output_handler;
+use crate::history::{entries_before_line, forget_line, ShellHistory};
+use crate::input_parser::needs_more_input;
+
+// `fc -l` without a range shows this many entries, like bash
+const DEFAULT_LIST_LENGTH: i64 = 16;
+
+// a number counts from the start of the list, a negative one back from the end,
+// anything else is the newest entry starting with it. Returns a 0-based index
+fn find_entry(entries: &[String], spec: &str) -> Option<usize> {
+    if let Ok(number) = spec.parse::<i64>() {
+        let length = entries.len() as i64;
+        let index = if number < 0 { length + number } else { number - 1 };
+        // out of range numbers are clamped, the way bash treats them
+        return (length > 0).then(|| index.clamp(0, length - 1) as usize);
+    }
+    entries.iter().rposition(|entry| entry.starts_with(spec))
+}
+
+// the entries between `first` and `last` and their numbers, in the order given
+fn select_range(entries: &[String], first: usize, last: usize) -> Vec<(usize, &String)> {
+    if first <= last {
+        (first..=last).map(|i| (i, &entries[i])).collect()
+    } else {
+        (last..=first).rev().map(|i| (i, &entries[i])).collect()
+    }
+}
+
+// a new file only this user can read, named by mkstemps so nobody can guess or plant it first
+fn create_temp_file() -> io::Result<(File, PathBuf)> {
+    let template = env::temp_dir().join("shelly-fc-XXXXXX.sh");
+    let mut bytes = CString::new(template.as_os_str().as_bytes())?.into_bytes_with_nul();
+    // SAFETY: a writable nul terminated template ending in XXXXXX and the 3 byte suffix
+    let fd = unsafe { libc::mkstemps(bytes.as_mut_ptr() as *mut libc::c_char, 3) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    bytes.pop();
+    // SAFETY: mkstemps just opened the descriptor and nothing else owns it
+    let file = unsafe { File::from_raw_fd(fd) };
+    Ok((file, PathBuf::from(OsString::from_vec(bytes))))
+}
+
+// the edited text runs command by command, a line that leaves one open takes the next ones with it
+fn split_commands(text: &str) -> Vec<String> {
+    let mut commands: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for line in text.lines() {
+        if current.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+        if !needs_more_input(&current) {
+            commands.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        commands.push(current);
+    }
+    commands
+}
+
+fn edit_commands(editor: &str, text: &str, command: &str) -> Result<Vec<String>, String> {
+    let (mut file, path) = create_temp_file().map_err(|e| format!("{}: {}", command, e))?;
+    let written = file.write_all(text.as_bytes());
+    drop(file);
+    if let Err(e) = written {
+        let _ = fs::remove_file(&path);
+        return Err(format!("{}: {}: {}", command, path.display(), e));
+    }
+
+    // $FCEDIT may carry its own arguments, like `code -w`
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = Command::new(program).args(words).arg(&path).status();
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(_) => return Ok(Vec::new()),
+        Err(e) => return Err(format!("{}: {}: {}", command, program, e)),
+    }
+    let edited = edited.map_err(|e| format!("{}: {}: {}", command, path.display(), e))?;
+    Ok(split_commands(&edited))
+}
+
+// returns the command lines to run, the caller echoes and executes them in order
+fn fc_output(
+    readline: &mut Editor<MyHelper, ShellHistory>,
+    args: &[String],
+    command: &str,
+    last_entry: &mut usize,
+) -> Result<(String, Vec<String>), String> {
+    let command = command.trim();
+    let mut list = false;
+    let mut numbers = true;
+    let mut reverse = false;
+    let mut substitute = false;
+    let mut editor: Option<String> = None;
+    let mut operands: Vec<&String> = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !operands.is_empty() || !arg.starts_with('-') || arg == "-" || arg.parse::<i64>().is_ok() {
+            operands.push(arg);
+            continue;
+        }
+        if arg == "--" {
+            operands.extend(args.by_ref());
+            break;
+        }
+        for flag in arg[1..].chars() {
+            match flag {
+                'l' => list = true,
+                'n' => numbers = false,
+                'r' => reverse = true,
+                's' => substitute = true,
+                'e' => match args.next() {
+                    Some(name) => editor = Some(name.clone()),
+                    None => return Err(format!("{}: -e: option requires an argument", command)),
+                },
+                _ => return Err(format!("{}: -{}: invalid option", command, flag)),
+            }
+        }
+    }
+    // `-e -` is the old spelling of -s
+    if editor.as_deref() == Some("-") {
+        substitute = true;
+    }
+
+    // ranges are about what came before the `fc` line
+    let entries = entries_before_line(readline);
+
+    if substitute {
+        let (replacement, spec) = match operands.first() {
+            Some(first) if first.contains('=') => (first.split_once('='), operands.get(1)),
+            first => (None, first),
+        };
+        let index = match spec {
+            Some(spec) => find_entry(&entries, spec),
+            None => entries.len().checked_sub(1),
+        };
+        let Some(index) = index else {
+            return Err(format!("{}: no command found", command));
+        };
+        let mut line = entries[index].clone();
+        if let Some((old, new)) = replacement
+            && !old.is_empty()
+        {
+            line = line.replace(old, new);
+        }
+        // `fc` and the commands it runs stand in for the `fc` line itself
+        forget_line(readline, last_entry);
+        return Ok((String::new(), vec![line]));
+    }
+
+    if operands.len() > 2 {
+        return Err(format!("{}: too many arguments", command));
+    }
+    let length = entries.len() as i64;
+    let default_first = if list { (-DEFAULT_LIST_LENGTH).max(-length).to_string() } else { "-1".to_string() };
+    let first_spec = operands.first().map_or(default_first, |spec| spec.to_string());
+    let first = find_entry(&entries, &first_spec);
+    let last = match operands.get(1) {
+        Some(spec) => find_entry(&entries, spec),
+        None if list => entries.len().checked_sub(1),
+        None => first,
+    };
+    let (Some(first), Some(last)) = (first, last) else {
+        return Err(format!("{}: history specification out of range", command));
+    };
+    let mut selected = select_range(&entries, first, last);
+    if reverse {
+        selected.reverse();
+    }
+
+    if list {
+        let lines: Vec<String> = selected
+            .iter()
+            .map(|(i, entry)| if numbers { format!("{}\t {}", i + 1, entry) } else { format!("\t {}", entry) })
+            .collect();
+        return Ok((lines.join("\n"), Vec::new()));
+    }
+
+    let editor = editor
+        .or_else(|| env::var("FCEDIT").ok().filter(|value| !value.is_empty()))
+        .or_else(|| env::var("EDITOR").ok().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| "vi".to_string());
+    let text: String = selected.iter().map(|(_, entry)| format!("{}\n", entry)).collect();
+    let commands = edit_commands(&editor, &text, command)?;
+    forget_line(readline, last_entry);
+    Ok((String::new(), commands))
+}
+
+pub fn fc_handler(
+    readline: &mut Editor<MyHelper, ShellHistory>,
+    args: &[String],
+    command: &str,
+    redirect: bool,
+    redirects: Vec<(String, String)>,
+    last_entry: &mut usize,
+) -> Vec<String> {
+    match fc_output(readline, args, command, last_entry) {
+        Ok((listing, commands)) => {
+            builtin_output_handler(Ok(listing), redirect, redirects);
+            commands
+        }
+        Err(msg) => {
+            builtin_output_handler(Err(msg), redirect, redirects);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn entries() -> Vec<String> {
+        ["ls", "cd /tmp", "make test", "make", "git status"].iter().map(|entry| entry.to_string()).collect()
+    }
+
+    #[test]
+    fn entries_by_number_or_prefix() {
+        let cases = [
+            ("1", Some(0)),
+            ("5", Some(4)),
+            ("-1", Some(4)),
+            ("-5", Some(0)),
+            // out of range numbers are clamped
+            ("9", Some(4)),
+            ("-9", Some(0)),
+            ("make", Some(3)),
+            ("make ", Some(2)),
+            ("c", Some(1)),
+            ("vim", None),
+        ];
+        for (spec, expected) in cases {
+            assert_eq!(find_entry(&entries(), spec), expected, "{:?}", spec);
+        }
+        assert_eq!(find_entry(&[], "1"), None);
+    }
+
+    #[test]
+    fn ranges_keep_the_order_given() {
+        let entries = entries();
+        let numbers = |first, last| select_range(&entries, first, last).iter().map(|(i, _)| *i).collect::<Vec<_>>();
+        assert_eq!(numbers(1, 3), [1, 2, 3]);
+        assert_eq!(numbers(3, 1), [3, 2, 1]);
+        assert_eq!(numbers(2, 2), [2]);
+    }
+
+    #[test]
+    fn edited_text_splits_into_whole_commands() {
+        let text = "echo one\n\n  \necho \"two\nlines\" |\nwc -l\ntrue &&\nfalse\necho 'left open\n";
+        assert_eq!(
+            split_commands(text),
+            ["echo one", "echo \"two\nlines\" |\nwc -l", "true &&\nfalse", "echo 'left open"]
+        );
+        assert!(split_commands("\n\n").is_empty());
+    }
+
+    #[test]
+    fn temp_files_are_private() {
+        let (file, path) = create_temp_file().unwrap();
+        let mode = file.metadata().unwrap().permissions().mode();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let _ = fs::remove_file(&path);
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(name.starts_with("shelly-fc-") && name.ends_with(".sh") && !name.contains("XXXXXX"));
+    }
+}
@@ -0,0 +1,350 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::handler::{builtin_output_handler, change_directory, describe_io_error};
+use crate::xdg::{data_file, lock_file, replace_file};
+
+// same ageing limit z.sh uses, once the ranks add up to this they all decay
+const MAX_TOTAL_RANK: f64 = 9000.0;
+
+struct Entry {
+    path: String,
+    rank: f64,
+    time: u64,
+}
+
+fn database_path() -> Option<PathBuf> {
+    data_file("frecency")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+// one `path|rank|time` line per directory, the format z.sh uses
+fn load_entries(path: &Path) -> Vec<Entry> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.rsplitn(3, '|');
+            let time = fields.next()?.parse().ok()?;
+            let rank = fields.next()?.parse().ok()?;
+            let path = fields.next()?.to_string();
+            Some(Entry { path, rank, time })
+        })
+        .collect()
+}
+
+fn save_entries(path: &Path, entries: &[Entry]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let contents: String = entries
+        .iter()
+        .map(|entry| format!("{}|{}|{}\n", entry.path, entry.rank, entry.time))
+        .collect();
+    replace_file(path, contents.as_bytes())
+}
+
+fn score(entry: &Entry, now: u64) -> f64 {
+    let age = now.saturating_sub(entry.time);
+    if age < 3600 {
+        entry.rank * 4.0
+    } else if age < 86400 {
+        entry.rank * 2.0
+    } else if age < 604800 {
+        entry.rank / 2.0
+    } else {
+        entry.rank / 4.0
+    }
+}
+
+// called after every successful directory change
+pub fn record_directory(directory: &str) {
+    if directory == "/" || env::var("HOME").is_ok_and(|home| home == directory) {
+        return;
+    }
+    let Some(database) = database_path() else {
+        return;
+    };
+    if let Some(parent) = database.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+
+    // held until the new database is in place, so two shells never lose each other's visit
+    let _lock = lock_file(&database, true);
+    let mut entries = load_entries(&database);
+    visit(&mut entries, directory, now());
+    let _ = save_entries(&database, &entries);
+}
+
+fn visit(entries: &mut Vec<Entry>, directory: &str, now: u64) {
+    match entries.iter_mut().find(|entry| entry.path == directory) {
+        Some(entry) => {
+            entry.rank += 1.0;
+            entry.time = now;
+        }
+        None => entries.push(Entry {
+            path: directory.to_string(),
+            rank: 1.0,
+            time: now,
+        }),
+    }
+
+    let total: f64 = entries.iter().map(|entry| entry.rank).sum();
+    if total > MAX_TOTAL_RANK {
+        for entry in entries.iter_mut() {
+            entry.rank *= 0.99;
+        }
+        entries.retain(|entry| entry.rank >= 1.0);
+    }
+}
+
+fn matches_keywords(path: &str, keywords: &[String], ignore_case: bool) -> bool {
+    let haystack = if ignore_case { path.to_lowercase() } else { path.to_string() };
+    let mut rest = haystack.as_str();
+    for keyword in keywords {
+        let needle = if ignore_case { keyword.to_lowercase() } else { keyword.clone() };
+        match rest.find(&needle) {
+            Some(index) => rest = &rest[index + needle.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[derive(Clone, Copy)]
+enum Ranking {
+    Frecency,
+    Rank,
+    Recent,
+}
+
+// existing directories matching every keyword in order, best first
+fn ranked_matches(keywords: &[String], ranking: Ranking) -> Vec<(f64, String)> {
+    let Some(database) = database_path() else {
+        return Vec::new();
+    };
+    rank_entries(&load_entries(&database), keywords, ranking, now())
+}
+
+fn rank_entries(entries: &[Entry], keywords: &[String], ranking: Ranking, now: u64) -> Vec<(f64, String)> {
+    let collect = |ignore_case: bool| -> Vec<(f64, String)> {
+        entries
+            .iter()
+            .filter(|entry| matches_keywords(&entry.path, keywords, ignore_case))
+            .filter(|entry| Path::new(&entry.path).is_dir())
+            .map(|entry| {
+                let value = match ranking {
+                    Ranking::Frecency => score(entry, now),
+                    Ranking::Rank => entry.rank,
+                    Ranking::Recent => entry.time as f64,
+                };
+                (value, entry.path.clone())
+            })
+            .collect()
+    };
+
+    // case only matters when it narrows things down, like z.sh
+    let mut matches = collect(false);
+    if matches.is_empty() {
+        matches = collect(true);
+    }
+    matches.sort_by(|a, b| b.0.total_cmp(&a.0));
+    matches
+}
+
+pub fn frecency_candidates(keywords: &[String]) -> Vec<String> {
+    ranked_matches(keywords, Ranking::Frecency)
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect()
+}
+
+fn forget_directory(directory: &str) -> Result<String, String> {
+    let Some(database) = database_path() else {
+        return Ok(String::new());
+    };
+    let _lock = lock_file(&database, true);
+    let mut entries = load_entries(&database);
+    entries.retain(|entry| entry.path != directory);
+    save_entries(&database, &entries)
+        .map(|_| String::new())
+        .map_err(|e| format!("z: could not update {}: {}", database.display(), describe_io_error(&e)))
+}
+
+fn z_output(args: &[String], command: &str) -> Result<String, String> {
+    let mut list = false;
+    let mut ranking = Ranking::Frecency;
+    let mut keywords: Vec<String> = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "-l" => list = true,
+            "-r" => ranking = Ranking::Rank,
+            "-t" => ranking = Ranking::Recent,
+            "-x" => {
+                let current = env::var("PWD").unwrap_or_default();
+                return forget_directory(&current);
+            }
+            "-h" | "--help" => {
+                return Ok(format!("{} [-l] [-r] [-t] [-x] keyword...", command.trim()));
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("{}: {}: invalid option", command.trim(), arg));
+            }
+            _ => keywords.push(arg.clone()),
+        }
+    }
+
+    let matches = ranked_matches(&keywords, ranking);
+
+    if list || keywords.is_empty() {
+        // lowest first so the best match ends up next to the prompt
+        let lines: Vec<String> = matches
+            .iter()
+            .rev()
+            .map(|(value, path)| format!("{:<10} {}", format_score(*value, ranking), path))
+            .collect();
+        return Ok(lines.join("\n"));
+    }
+
+    let Some((_, best)) = matches.first() else {
+        return Err(format!("{}: no match for {}", command.trim(), keywords.join(" ")));
+    };
+    change_directory(Path::new(best), false)
+        .map(|_| String::new())
+        .map_err(|e| format!("{}: {}: {}", command.trim(), best, describe_io_error(&e)))
+}
+
+fn format_score(value: f64, ranking: Ranking) -> String {
+    match ranking {
+        Ranking::Recent => format!("{}", value as u64),
+        _ => format!("{:.1}", value),
+    }
+}
+
+pub fn z_handler(
+    args: &[String],
+    command: &str,
+    redirect: bool,
+    redirects: Vec<(String, String)>,
+) {
+    builtin_output_handler(z_output(args, command), redirect, redirects);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, rank: f64, time: u64) -> Entry {
+        Entry { path: path.to_string(), rank, time }
+    }
+
+    #[test]
+    fn scores_age_with_the_last_visit() {
+        let now = 1_000_000;
+        let cases = [
+            (0, 40.0),
+            (3599, 40.0),
+            (3600, 20.0),
+            (86399, 20.0),
+            (86400, 5.0),
+            (604799, 5.0),
+            (604800, 2.5),
+            // a clock that went backwards counts as a fresh visit
+            (-10, 40.0),
+        ];
+        for (age, expected) in cases {
+            let time = (now as i64 - age) as u64;
+            assert_eq!(score(&entry("/tmp", 10.0, time), now), expected, "{}", age);
+        }
+    }
+
+    #[test]
+    fn visits_add_rank_and_age_the_rest() {
+        let mut entries = vec![entry("/a", 2.0, 10)];
+        visit(&mut entries, "/a", 20);
+        visit(&mut entries, "/b", 30);
+        let visited: Vec<_> = entries.iter().map(|e| (e.path.as_str(), e.rank, e.time)).collect();
+        assert_eq!(visited, [("/a", 3.0, 20), ("/b", 1.0, 30)]);
+
+        // going over the limit shrinks every rank and drops the ones under 1
+        let mut entries = vec![entry("/big", MAX_TOTAL_RANK, 10), entry("/small", 1.0, 10)];
+        visit(&mut entries, "/big", 20);
+        let aged: Vec<_> = entries.iter().map(|e| (e.path.as_str(), e.rank)).collect();
+        assert_eq!(aged, [("/big", (MAX_TOTAL_RANK + 1.0) * 0.99)]);
+    }
+
+    #[test]
+    fn keywords_match_in_order() {
+        let cases = [
+            (&["foo"][..], false, true),
+            (&["src", "shelly"][..], false, false),
+            (&["shelly", "src"][..], false, true),
+            (&["Shelly"][..], false, false),
+            (&["Shelly"][..], true, true),
+            (&["ll", "ll"][..], false, false),
+            (&[][..], false, true),
+        ];
+        for (keywords, ignore_case, expected) in cases {
+            let keywords: Vec<String> = keywords.iter().map(|k| k.to_string()).collect();
+            assert_eq!(matches_keywords("/home/foo/shelly/src", &keywords, ignore_case), expected, "{:?}", keywords);
+        }
+    }
+
+    #[test]
+    fn ranks_existing_matches_best_first() {
+        let root = env::temp_dir().join(format!("shelly-frecency-{}", std::process::id()));
+        for dir in ["old", "new", "Other"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let at = |dir: &str| format!("{}/{}", root.display(), dir);
+        let now = 1_000_000;
+        let entries = vec![
+            entry(&at("old"), 10.0, now - 604800),
+            entry(&at("new"), 2.0, now),
+            entry(&at("gone"), 50.0, now),
+            entry(&at("Other"), 1.0, now - 100),
+        ];
+        let names = |keywords: &[&str], ranking: Ranking| -> Vec<String> {
+            let keywords: Vec<String> = keywords.iter().map(|k| k.to_string()).collect();
+            rank_entries(&entries, &keywords, ranking, now)
+                .into_iter()
+                .map(|(_, path)| path.rsplit('/').next().unwrap().to_string())
+                .collect()
+        };
+        let cases = [
+            (names(&[], Ranking::Frecency), vec!["new", "Other", "old"]),
+            (names(&[], Ranking::Rank), vec!["old", "new", "Other"]),
+            (names(&[], Ranking::Recent), vec!["new", "Other", "old"]),
+            // case is ignored only when nothing matches with it
+            (names(&["other"], Ranking::Frecency), vec!["Other"]),
+            (names(&["O"], Ranking::Frecency), vec!["Other"]),
+        ];
+        let _ = fs::remove_dir_all(&root);
+        for (got, expected) in cases {
+            assert_eq!(got, expected);
+        }
+    }
+
+    #[test]
+    fn database_round_trip() {
+        let path = env::temp_dir().join(format!("shelly-frecency-db-{}", std::process::id()));
+        fs::write(&path, "/a|b|2|100\n/c|1.5|200\nbroken\n/d|x|300\n").unwrap();
+        let entries = load_entries(&path);
+        save_entries(&path, &entries).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        // a `|` in the path is kept, lines that do not parse are dropped
+        assert_eq!(saved, "/a|b|2|100\n/c|1.5|200\n");
+    }
+}
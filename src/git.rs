@@ -0,0 +1,152 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// object names as git stores them, hex is only for showing and parsing
+pub type ObjectId = [u8; 20];
+
+// the work tree and .git directory for `start` or the closest parent, following the
+// `gitdir:` file worktrees and submodules use
+pub fn find_repository(start: &Path) -> Option<(PathBuf, PathBuf)> {
+    for dir in start.ancestors() {
+        let candidate = dir.join(".git");
+        if candidate.is_dir() {
+            return Some((dir.to_path_buf(), candidate));
+        }
+        if candidate.is_file() {
+            let contents = fs::read_to_string(&candidate).ok()?;
+            let target = contents.strip_prefix("gitdir:")?.trim();
+            return Some((dir.to_path_buf(), dir.join(target)));
+        }
+    }
+    None
+}
+
+pub fn find_git_dir(start: &Path) -> Option<PathBuf> {
+    find_repository(start).map(|(_, git_dir)| git_dir)
+}
+
+pub fn parse_hex(hex: &str) -> Option<ObjectId> {
+    let hex = hex.as_bytes();
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut id = [0u8; 20];
+    for (byte, pair) in id.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(id)
+}
+
+pub fn to_hex(id: &ObjectId) -> String {
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// worktrees keep refs and packed-refs in the main repository, named by `commondir`
+pub fn common_dir(git_dir: &Path) -> PathBuf {
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common) => git_dir.join(common.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+fn collect_loose_refs(dir: &Path, prefix: &str, names: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let full = format!("{}{}", prefix, name);
+        if entry.path().is_dir() {
+            collect_loose_refs(&entry.path(), &format!("{}/", full), names);
+        } else {
+            names.push(full);
+        }
+    }
+}
+
+// ref names under `namespace` (like `refs/heads/`), loose files and packed-refs together
+pub fn ref_names(git_dir: &Path, namespace: &str) -> Vec<String> {
+    let common = common_dir(git_dir);
+    let mut names = Vec::new();
+    collect_loose_refs(&common.join(namespace), "", &mut names);
+
+    if let Ok(packed) = fs::read_to_string(common.join("packed-refs")) {
+        for line in packed.lines() {
+            // comments and the `^` lines peeled tags add
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            if let Some((_, name)) = line.split_once(' ')
+                && let Some(short) = name.strip_prefix(namespace)
+            {
+                names.push(short.to_string());
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn packed_ref(common: &Path, name: &str) -> Option<ObjectId> {
+    let packed = fs::read_to_string(common.join("packed-refs")).ok()?;
+    packed.lines().find_map(|line| {
+        let (id, packed_name) = line.split_once(' ')?;
+        if packed_name == name { parse_hex(id) } else { None }
+    })
+}
+
+// what `name` (`HEAD`, `refs/heads/main`) points at, following symbolic refs. HEAD and the
+// other per worktree refs are in the .git directory, the shared ones in the common one
+pub fn resolve_ref(git_dir: &Path, name: &str) -> Option<ObjectId> {
+    let common = common_dir(git_dir);
+    let mut name = name.to_string();
+    // symbolic refs can point at each other, git gives up after five
+    for _ in 0..5 {
+        let contents = fs::read_to_string(git_dir.join(&name)).or_else(|_| fs::read_to_string(common.join(&name)));
+        let Ok(contents) = contents else {
+            return packed_ref(&common, &name);
+        };
+        match contents.trim().strip_prefix("ref:") {
+            Some(target) => name = target.trim().to_string(),
+            None => return parse_hex(contents.trim()),
+        }
+    }
+    None
+}
+
+// the last `key` of `[section "subsection"]` in the repository config
+pub fn config_value(git_dir: &Path, section: &str, subsection: &str, key: &str) -> Option<String> {
+    let config = fs::read_to_string(common_dir(git_dir).join("config")).ok()?;
+    let wanted = format!("[{} \"{}\"]", section, subsection);
+    let mut inside = false;
+    let mut value = None;
+    for line in config.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            inside = line == wanted;
+        } else if inside
+            && let Some((name, found)) = line.split_once('=')
+            && name.trim().eq_ignore_ascii_case(key)
+        {
+            value = Some(found.trim().trim_matches('"').to_string());
+        }
+    }
+    value
+}
+
+// `[remote "origin"]` sections of the repository config
+pub fn remote_names(git_dir: &Path) -> Vec<String> {
+    let Ok(config) = fs::read_to_string(common_dir(git_dir).join("config")) else {
+        return Vec::new();
+    };
+    config
+        .lines()
+        .filter_map(|line| {
+            let section = line.trim().strip_prefix("[remote \"")?;
+            Some(section.strip_suffix("\"]")?.to_string())
+        })
+        .collect()
+}
@@ -0,0 +1,580 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::git::{common_dir, parse_hex, to_hex, ObjectId};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ObjectKind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+// shared so cached delta bases are not copied
+pub type Object = (ObjectKind, Rc<Vec<u8>>);
+
+// delta chains are usually well under this, git itself stops at 4095 when writing
+const MAX_DELTA_DEPTH: usize = 512;
+// decoded delta bases kept around, commits walked one after another share them a lot
+const BASE_CACHE_SIZE: usize = 256;
+// sizes come from the repository, anything bigger is taken as corrupt rather than allocated
+const MAX_OBJECT_SIZE: usize = 256 << 20;
+
+// a zlib stream that must come out as exactly `size` bytes, adler-32 checked
+fn inflate_exact(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    if size > MAX_OBJECT_SIZE {
+        return None;
+    }
+    // one byte of room so a stream longer than promised fails instead of stopping short
+    let output = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, size + 1).ok()?;
+    (output.len() == size).then_some(output)
+}
+
+// a pack file and its version 2 index, read with pread so huge packs are never loaded whole
+struct Pack {
+    index: File,
+    data: File,
+    length: u64,
+    fanout: Vec<u32>,
+}
+
+impl Pack {
+    fn open(index_path: &Path) -> Option<Self> {
+        let index = File::open(index_path).ok()?;
+        let data = File::open(index_path.with_extension("pack")).ok()?;
+        let length = data.metadata().ok()?.len();
+        let mut header = vec![0u8; 8 + 256 * 4];
+        index.read_exact_at(&mut header, 0).ok()?;
+        if header[..8] != [0xff, b't', b'O', b'c', 0, 0, 0, 2] {
+            return None;
+        }
+        let fanout = header[8..]
+            .chunks_exact(4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        Some(Pack { index, data, length, fanout })
+    }
+
+    fn read_u32(&self, offset: u64) -> Option<u32> {
+        let mut bytes = [0u8; 4];
+        self.index.read_exact_at(&mut bytes, offset).ok()?;
+        Some(u32::from_be_bytes(bytes))
+    }
+
+    // where `id` starts in the pack, by binary search over the sorted names in the index
+    fn find(&self, id: &ObjectId) -> Option<u64> {
+        let count = self.fanout[255] as u64;
+        let mut low = if id[0] == 0 { 0 } else { self.fanout[id[0] as usize - 1] as u64 };
+        let mut high = self.fanout[id[0] as usize] as u64;
+        let names = 8 + 256 * 4;
+        while low < high {
+            let middle = (low + high) / 2;
+            let mut name = [0u8; 20];
+            self.index.read_exact_at(&mut name, names + middle * 20).ok()?;
+            match name.cmp(id) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    // after the names come a crc per object, then the offsets
+                    let offsets = names + count * 24;
+                    let offset = self.read_u32(offsets + middle * 4)?;
+                    if offset & 0x8000_0000 == 0 {
+                        return Some(offset as u64);
+                    }
+                    // packs over 2GB keep big offsets in a table of their own
+                    let large = offsets + count * 4 + (offset & 0x7fff_ffff) as u64 * 8;
+                    let mut bytes = [0u8; 8];
+                    self.index.read_exact_at(&mut bytes, large).ok()?;
+                    return Some(u64::from_be_bytes(bytes));
+                }
+            }
+        }
+        None
+    }
+
+    // up to `length` bytes at `offset`, fewer at the end of the file
+    fn read_data(&self, offset: u64, length: usize) -> Vec<u8> {
+        let length = length.min(self.length.saturating_sub(offset) as usize);
+        let mut buffer = vec![0u8; length];
+        let mut filled = 0;
+        while filled < length {
+            match self.data.read_at(&mut buffer[filled..], offset + filled as u64) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => filled += read,
+            }
+        }
+        buffer.truncate(filled);
+        buffer
+    }
+}
+
+enum Entry {
+    Whole(ObjectKind),
+    OffsetDelta(u64),
+    RefDelta(ObjectId),
+}
+
+fn kind_from_name(name: &[u8]) -> Option<ObjectKind> {
+    match name {
+        b"commit" => Some(ObjectKind::Commit),
+        b"tree" => Some(ObjectKind::Tree),
+        b"blob" => Some(ObjectKind::Blob),
+        b"tag" => Some(ObjectKind::Tag),
+        _ => None,
+    }
+}
+
+// the little endian base 128 sizes at the start of a delta
+fn delta_size(delta: &[u8], position: &mut usize) -> Option<usize> {
+    let mut size = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *delta.get(*position)?;
+        *position += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 || shift > 56 {
+            return Some(size);
+        }
+    }
+}
+
+// rebuilds an object from its base and a delta of copy and insert instructions
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 0;
+    if delta_size(delta, &mut position)? != base.len() {
+        return None;
+    }
+    let size = delta_size(delta, &mut position)?;
+    if size > MAX_OBJECT_SIZE {
+        return None;
+    }
+    let mut result: Vec<u8> = Vec::with_capacity(size.min(1 << 20));
+    while position < delta.len() {
+        let op = delta[position];
+        position += 1;
+        if op & 0x80 != 0 {
+            // which offset and size bytes follow is given by the low seven bits
+            let mut values = [0usize; 2];
+            for (bit, (value, shift)) in [(0, 0), (0, 8), (0, 16), (0, 24), (1, 0), (1, 8), (1, 16)].iter().enumerate() {
+                if op & (1 << bit) != 0 {
+                    values[*value] |= (*delta.get(position)? as usize) << shift;
+                    position += 1;
+                }
+            }
+            let [offset, length] = values;
+            let length = if length == 0 { 0x10000 } else { length };
+            result.extend_from_slice(base.get(offset..offset.checked_add(length)?)?);
+        } else if op != 0 {
+            result.extend_from_slice(delta.get(position..position + op as usize)?);
+            position += op as usize;
+        } else {
+            return None;
+        }
+        if result.len() > size {
+            return None;
+        }
+    }
+    (result.len() == size).then_some(result)
+}
+
+// the loose objects and packs of a repository
+pub struct ObjectStore {
+    objects: PathBuf,
+    packs: Vec<Pack>,
+    bases: RefCell<HashMap<(usize, u64), Object>>,
+}
+
+impl ObjectStore {
+    pub fn open(git_dir: &Path) -> Self {
+        let objects = common_dir(git_dir).join("objects");
+        let mut packs = Vec::new();
+        if let Ok(entries) = fs::read_dir(objects.join("pack")) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_some_and(|extension| extension == "idx")
+                    && let Some(pack) = Pack::open(&path)
+                {
+                    packs.push(pack);
+                }
+            }
+        }
+        ObjectStore {
+            objects,
+            packs,
+            bases: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn read(&self, id: &ObjectId) -> Option<Object> {
+        self.read_at_depth(id, 0)
+    }
+
+    fn read_at_depth(&self, id: &ObjectId, depth: usize) -> Option<Object> {
+        for (number, pack) in self.packs.iter().enumerate() {
+            if let Some(offset) = pack.find(id) {
+                return self.read_packed(number, offset, depth);
+            }
+        }
+        self.read_loose(id)
+    }
+
+    fn read_loose(&self, id: &ObjectId) -> Option<Object> {
+        let hex = to_hex(id);
+        let compressed = fs::read(self.objects.join(&hex[..2]).join(&hex[2..])).ok()?;
+        // the header is short, the size it gives bounds the rest
+        let mut data = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(&compressed, MAX_OBJECT_SIZE + 64).ok()?;
+        // `<kind> <size>\0` comes before the contents
+        let end = data.iter().position(|byte| *byte == 0)?;
+        let space = data[..end].iter().position(|byte| *byte == b' ')?;
+        let kind = kind_from_name(&data[..space])?;
+        let size: usize = std::str::from_utf8(&data[space + 1..end]).ok()?.parse().ok()?;
+        if data.len() - end - 1 != size {
+            return None;
+        }
+        data.drain(..=end);
+        Some((kind, Rc::new(data)))
+    }
+
+    fn read_packed(&self, number: usize, offset: u64, depth: usize) -> Option<Object> {
+        if depth > MAX_DELTA_DEPTH {
+            return None;
+        }
+        if let Some(cached) = self.bases.borrow().get(&(number, offset)) {
+            return Some(cached.clone());
+        }
+        let pack = &self.packs[number];
+        // the header: type and size in a varint, then where a delta's base is
+        let header = pack.read_data(offset, 32);
+        let mut position = 0;
+        let mut byte = *header.first()?;
+        let kind = (byte >> 4) & 7;
+        let mut size = (byte & 0x0f) as usize;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            position += 1;
+            byte = *header.get(position)?;
+            if shift > 57 {
+                return None;
+            }
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+        if size > MAX_OBJECT_SIZE {
+            return None;
+        }
+        position += 1;
+        let entry = match kind {
+            1 => Entry::Whole(ObjectKind::Commit),
+            2 => Entry::Whole(ObjectKind::Tree),
+            3 => Entry::Whole(ObjectKind::Blob),
+            4 => Entry::Whole(ObjectKind::Tag),
+            6 => {
+                let mut byte = *header.get(position)?;
+                position += 1;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    byte = *header.get(position)?;
+                    position += 1;
+                    distance = distance.checked_add(1)?.checked_mul(128)? | (byte & 0x7f) as u64;
+                }
+                Entry::OffsetDelta(offset.checked_sub(distance)?)
+            }
+            7 => {
+                let base: ObjectId = header.get(position..position + 20)?.try_into().ok()?;
+                position += 20;
+                Entry::RefDelta(base)
+            }
+            _ => return None,
+        };
+
+        // deflate never grows data by more than a few bytes per 64k block
+        let compressed = pack.read_data(offset + position as u64, size + size / 1000 + 64);
+        let data = inflate_exact(&compressed, size)?;
+        let (kind, data) = match entry {
+            Entry::Whole(kind) => (kind, Rc::new(data)),
+            Entry::OffsetDelta(base_offset) => {
+                let (kind, base) = self.read_packed(number, base_offset, depth + 1)?;
+                (kind, Rc::new(apply_delta(&base, &data)?))
+            }
+            Entry::RefDelta(base_id) => {
+                let (kind, base) = self.read_at_depth(&base_id, depth + 1)?;
+                (kind, Rc::new(apply_delta(&base, &data)?))
+            }
+        };
+
+        let mut bases = self.bases.borrow_mut();
+        if bases.len() >= BASE_CACHE_SIZE {
+            bases.clear();
+        }
+        bases.insert((number, offset), (kind, data.clone()));
+        Some((kind, data))
+    }
+}
+
+pub struct Commit {
+    pub tree: ObjectId,
+    pub parents: Vec<ObjectId>,
+    pub time: i64,
+}
+
+// the tree, parents and committer time from the header lines of a commit
+pub fn parse_commit(data: &[u8]) -> Option<Commit> {
+    let text = String::from_utf8_lossy(data);
+    let mut tree = None;
+    let mut parents = Vec::new();
+    let mut time = 0;
+    for line in text.lines() {
+        if line.is_empty() {
+            break;
+        }
+        if let Some(hex) = line.strip_prefix("tree ") {
+            tree = parse_hex(hex);
+        } else if let Some(hex) = line.strip_prefix("parent ") {
+            parents.push(parse_hex(hex)?);
+        } else if let Some(committer) = line.strip_prefix("committer ") {
+            // `name <email> 1700000000 +0100`
+            time = committer.rsplit(' ').nth(1).and_then(|seconds| seconds.parse().ok()).unwrap_or(0);
+        }
+    }
+    Some(Commit { tree: tree?, parents, time })
+}
+
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: Vec<u8>,
+    pub id: ObjectId,
+}
+
+// `<octal mode> <name>\0<20 byte id>` repeated
+pub fn parse_tree(data: &[u8]) -> Option<Vec<TreeEntry>> {
+    let mut entries = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|byte| *byte == b' ')?;
+        let mode = u32::from_str_radix(std::str::from_utf8(&rest[..space]).ok()?, 8).ok()?;
+        let nul = space + rest[space..].iter().position(|byte| *byte == 0)?;
+        let name = rest[space + 1..nul].to_vec();
+        let id: ObjectId = rest.get(nul + 1..nul + 21)?.try_into().ok()?;
+        entries.push(TreeEntry { mode, name, id });
+        rest = &rest[nul + 21..];
+    }
+    Some(entries)
+}
+
+pub struct IndexEntry {
+    pub path: Vec<u8>,
+    pub id: ObjectId,
+    pub mode: u32,
+    pub mtime: u32,
+    pub size: u32,
+    // 0 normally, 1 to 3 for the sides of a merge conflict
+    pub stage: u16,
+    pub skip_worktree: bool,
+}
+
+pub struct Index {
+    pub entries: Vec<IndexEntry>,
+    // the tree the whole index would be written as, when the cache-tree extension still has it
+    pub root_tree: Option<ObjectId>,
+    // when the index file was written, in seconds; a file changed in that same second can
+    // still match its entry's size and mtime
+    pub mtime: u32,
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+// the cache-tree extension starts with the root: `\0<entries> <subtrees>\n<id>`, where
+// an entry count of -1 means it went stale
+fn cached_root_tree(extension: &[u8]) -> Option<ObjectId> {
+    let rest = extension.strip_prefix(b"\0")?;
+    let newline = rest.iter().position(|byte| *byte == b'\n')?;
+    let counts = std::str::from_utf8(&rest[..newline]).ok()?;
+    let entries: i64 = counts.split(' ').next()?.parse().ok()?;
+    if entries < 0 {
+        return None;
+    }
+    rest.get(newline + 1..newline + 21)?.try_into().ok()
+}
+
+// the `DIRC` index file, versions 2 to 4
+pub fn read_index(git_dir: &Path) -> Option<Index> {
+    let path = git_dir.join("index");
+    let mtime = fs::metadata(&path).ok()?.mtime() as u32;
+    parse_index(&fs::read(&path).ok()?, mtime)
+}
+
+fn parse_index(data: &[u8], written: u32) -> Option<Index> {
+    if data.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = be_u32(data, 4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let count = be_u32(data, 8)? as usize;
+    // every entry takes at least 62 bytes, a count past that is corrupt
+    let mut entries: Vec<IndexEntry> = Vec::with_capacity(count.min(data.len() / 62));
+    let mut position = 12;
+    let mut previous: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let start = position;
+        // ctime and mtime as seconds and nanoseconds, then dev, ino, mode, uid, gid, size
+        let mtime = be_u32(data, start + 8)?;
+        let mode = be_u32(data, start + 24)?;
+        let size = be_u32(data, start + 36)?;
+        let id: ObjectId = data.get(start + 40..start + 60)?.try_into().ok()?;
+        let flags = u16::from_be_bytes(data.get(start + 60..start + 62)?.try_into().ok()?);
+        position = start + 62;
+        let mut skip_worktree = false;
+        if flags & 0x4000 != 0 {
+            let extended = u16::from_be_bytes(data.get(position..position + 2)?.try_into().ok()?);
+            skip_worktree = extended & 0x4000 != 0;
+            position += 2;
+        }
+        let path = if version == 4 {
+            // paths drop the end of the previous path given by a varint, then add a suffix
+            let mut byte = *data.get(position)?;
+            position += 1;
+            let mut strip = (byte & 0x7f) as usize;
+            while byte & 0x80 != 0 {
+                byte = *data.get(position)?;
+                position += 1;
+                strip = strip.checked_add(1)?.checked_mul(128)? | (byte & 0x7f) as usize;
+            }
+            let nul = position + data.get(position..)?.iter().position(|byte| *byte == 0)?;
+            let mut path = previous.get(..previous.len().checked_sub(strip)?)?.to_vec();
+            path.extend_from_slice(&data[position..nul]);
+            position = nul + 1;
+            path
+        } else {
+            let nul = position + data.get(position..)?.iter().position(|byte| *byte == 0)?;
+            let path = data[position..nul].to_vec();
+            // entries are padded with nuls to a multiple of eight bytes
+            position = start + (nul - start + 8) / 8 * 8;
+            path
+        };
+        previous = path.clone();
+        entries.push(IndexEntry {
+            path,
+            id,
+            mode,
+            mtime,
+            size,
+            stage: (flags >> 12) & 3,
+            skip_worktree,
+        });
+    }
+
+    let mut root_tree = None;
+    // extensions until the checksum in the last 20 bytes
+    while position + 8 <= data.len().saturating_sub(20) {
+        let signature = &data[position..position + 4];
+        let length = be_u32(data, position + 4)? as usize;
+        let body = data.get(position + 8..position + 8 + length)?;
+        if signature == b"TREE" {
+            root_tree = cached_root_tree(body);
+        }
+        position += 8 + length;
+    }
+    Some(Index { entries, root_tree, mtime: written })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STORED: [u8; 30] = [
+        120, 1, 1, 19, 0, 236, 255, 104, 101, 108, 108, 111, 44, 32, 115, 116, 111, 114, 101, 100,
+        32, 98, 108, 111, 99, 107, 70, 206, 7, 29,
+    ];
+    const FIXED: [u8; 16] = [120, 218, 203, 72, 205, 201, 201, 87, 200, 64, 39, 1, 104, 3, 8, 177];
+
+    #[test]
+    fn inflate_known_answers() {
+        assert_eq!(inflate_exact(&STORED, 19).unwrap(), b"hello, stored block");
+        assert_eq!(inflate_exact(&FIXED, 23).unwrap(), b"hello hello hello hello");
+    }
+
+    #[test]
+    fn inflate_rejects_bad_streams() {
+        // the size the pack header promised is not what came out
+        assert_eq!(inflate_exact(&FIXED, 22), None);
+        assert_eq!(inflate_exact(&FIXED, 24), None);
+        // adler-32 of the output does not match
+        let mut corrupt = FIXED;
+        corrupt[15] ^= 1;
+        assert_eq!(inflate_exact(&corrupt, 23), None);
+        // NLEN of a stored block is not the complement of LEN
+        let mut corrupt = STORED;
+        corrupt[5] ^= 1;
+        assert_eq!(inflate_exact(&corrupt, 19), None);
+        assert_eq!(inflate_exact(&FIXED, 1 << 40), None);
+    }
+
+    #[test]
+    fn delta_copies_and_inserts() {
+        // copy "hello " from the base, insert "shelly ", copy "world"
+        let mut delta = vec![11, 18, 0x90, 6, 7];
+        delta.extend_from_slice(b"shelly ");
+        delta.extend_from_slice(&[0x91, 6, 5]);
+        assert_eq!(apply_delta(b"hello world", &delta).unwrap(), b"hello shelly world");
+        // the base is not the one the delta was made against
+        assert_eq!(apply_delta(b"hello", &delta), None);
+    }
+
+    #[test]
+    fn delta_sizes_are_bounded() {
+        // a result size of 2^40 is refused before anything is allocated
+        let delta = [11, 0x80, 0x80, 0x80, 0x80, 0x80, 0x20, 0x90, 5];
+        assert_eq!(apply_delta(b"hello world", &delta), None);
+        // copies past the promised size stop the delta
+        let delta = [11, 5, 0x90, 11];
+        assert_eq!(apply_delta(b"hello world", &delta), None);
+    }
+
+    fn check_index(index: Index) {
+        let entries: Vec<_> = index
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_slice(), entry.mode, to_hex(&entry.id)))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (&b"a.txt"[..], 0o100644, "ce013625030ba8dba906f756967f9e9ca394464a".to_string()),
+                (&b"dir/b.txt"[..], 0o100644, "af9c6fd168ea28cf99aa2c2dd9057a8b720e2262".to_string()),
+                (&b"dir/c.txt"[..], 0o100755, "558ad609198f9083259cf1823181bd14daa3d0ef".to_string()),
+            ]
+        );
+        assert_eq!(index.entries[0].size, 6);
+        assert!(index.entries.iter().all(|entry| entry.stage == 0 && !entry.skip_worktree));
+        assert_eq!(index.root_tree, parse_hex("2916fb61807eee670728135ae7b66cf6efd3387c"));
+        assert_eq!(index.mtime, 7);
+    }
+
+    #[test]
+    fn index_v2() {
+        check_index(parse_index(include_bytes!("../tests/fixtures/index-v2"), 7).unwrap());
+    }
+
+    #[test]
+    fn index_v4() {
+        // version 4 strips the prefix each path shares with the one before
+        check_index(parse_index(include_bytes!("../tests/fixtures/index-v4"), 7).unwrap());
+    }
+
+    #[test]
+    fn index_counts_are_bounded() {
+        let mut data = include_bytes!("../tests/fixtures/index-v2").to_vec();
+        data[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(parse_index(&data, 7).is_none());
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::{BinaryHeap, HashMap};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::git::{config_value, find_repository, resolve_ref, to_hex, ObjectId};
+use crate::git_objects::{parse_commit, parse_tree, read_index, Index, ObjectKind, ObjectStore};
+use crate::history_store::current_directory;
+use crate::sha1::sha1;
+
+// how long the prompt waits for the dirty, staged and ahead/behind checks, in
+// milliseconds. SHELLY_GIT_TIMEOUT changes it, 0 shows only the branch
+const DEFAULT_TIMEOUT_MS: u64 = 200;
+
+const FROM_LOCAL: u8 = 1;
+const FROM_UPSTREAM: u8 = 2;
+const FROM_BOTH: u8 = FROM_LOCAL | FROM_UPSTREAM;
+
+const GITLINK_MODE: u32 = 0o160000;
+const SYMLINK_MODE: u32 = 0o120000;
+const TREE_MODE: u32 = 0o040000;
+
+// the parts that need objects or the whole index, None where the time ran out
+struct Details {
+    dirty: Option<bool>,
+    staged: Option<bool>,
+    ahead_behind: Option<(usize, usize)>,
+}
+
+fn timeout() -> Duration {
+    let millis = env::var("SHELLY_GIT_TIMEOUT")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_TIMEOUT_MS);
+    Duration::from_millis(millis)
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|contents| contents.trim().to_string())
+}
+
+// ` 2/5` from the step files a rebase or am leaves behind
+fn progress(dir: &Path, step: &str, total: &str) -> String {
+    match (read_trimmed(&dir.join(step)), read_trimmed(&dir.join(total))) {
+        (Some(step), Some(total)) if !step.is_empty() && !total.is_empty() => format!(" {}/{}", step, total),
+        _ => String::new(),
+    }
+}
+
+// the operation in progress, named like git-prompt.sh does, and the branch a rebase
+// started from since HEAD is detached while it runs
+fn operation(git_dir: &Path) -> (Option<String>, Option<String>) {
+    let merge = git_dir.join("rebase-merge");
+    if merge.is_dir() {
+        let state = format!("REBASE{}", progress(&merge, "msgnum", "end"));
+        return (Some(state), read_trimmed(&merge.join("head-name")));
+    }
+    let apply = git_dir.join("rebase-apply");
+    if apply.is_dir() {
+        let name = if apply.join("rebasing").exists() {
+            "REBASE"
+        } else if apply.join("applying").exists() {
+            "AM"
+        } else {
+            "AM/REBASE"
+        };
+        let state = format!("{}{}", name, progress(&apply, "next", "last"));
+        return (Some(state), read_trimmed(&apply.join("head-name")));
+    }
+    let markers = [
+        ("MERGE_HEAD", "MERGING"),
+        ("CHERRY_PICK_HEAD", "CHERRY-PICKING"),
+        ("REVERT_HEAD", "REVERTING"),
+        ("BISECT_LOG", "BISECTING"),
+    ];
+    let state = markers
+        .iter()
+        .find(|(file, _)| git_dir.join(file).exists())
+        .map(|(_, name)| name.to_string());
+    (state, None)
+}
+
+// the commit the branch's upstream is at, from `branch.<name>.remote` and `.merge`
+fn upstream(git_dir: &Path, branch: &str) -> Option<ObjectId> {
+    let remote = config_value(git_dir, "branch", branch, "remote")?;
+    let merge = config_value(git_dir, "branch", branch, "merge")?;
+    let name = if remote == "." {
+        merge
+    } else {
+        format!("refs/remotes/{}/{}", remote, merge.strip_prefix("refs/heads/")?)
+    };
+    resolve_ref(git_dir, &name)
+}
+
+// the committer time of `id`, reading the commit the first time
+fn load_commit(store: &ObjectStore, commits: &mut HashMap<ObjectId, (i64, Vec<ObjectId>)>, id: ObjectId) -> Option<i64> {
+    if let Some((time, _)) = commits.get(&id) {
+        return Some(*time);
+    }
+    let (kind, data) = store.read(&id)?;
+    if kind != ObjectKind::Commit {
+        return None;
+    }
+    let commit = parse_commit(&data)?;
+    commits.insert(id, (commit.time, commit.parents));
+    Some(commit.time)
+}
+
+// commits only the local branch has and commits only the upstream has. Walks both
+// histories newest first, marking which side reaches each commit, and stops once
+// everything left to walk is reachable from both
+fn ahead_behind(store: &ObjectStore, local: ObjectId, upstream: ObjectId, deadline: Instant) -> Option<(usize, usize)> {
+    if local == upstream {
+        return Some((0, 0));
+    }
+    let mut flags: HashMap<ObjectId, u8> = HashMap::new();
+    let mut commits: HashMap<ObjectId, (i64, Vec<ObjectId>)> = HashMap::new();
+    let mut queue: BinaryHeap<(i64, ObjectId)> = BinaryHeap::new();
+    flags.insert(local, FROM_LOCAL);
+    flags.insert(upstream, FROM_UPSTREAM);
+    queue.push((load_commit(store, &mut commits, local)?, local));
+    queue.push((load_commit(store, &mut commits, upstream)?, upstream));
+
+    while let Some((_, id)) = queue.pop() {
+        if Instant::now() >= deadline {
+            return None;
+        }
+        let flag = flags[&id];
+        for parent in commits[&id].1.clone() {
+            let known = flags.entry(parent).or_insert(0);
+            if *known | flag != *known {
+                *known |= flag;
+                queue.push((load_commit(store, &mut commits, parent)?, parent));
+            }
+        }
+        if queue.iter().all(|(_, id)| flags[id] == FROM_BOTH) {
+            break;
+        }
+    }
+    let ahead = flags.values().filter(|flag| **flag == FROM_LOCAL).count();
+    let behind = flags.values().filter(|flag| **flag == FROM_UPSTREAM).count();
+    Some((ahead, behind))
+}
+
+// tracked files that differ from the index. Files whose size and mtime still match what
+// the index recorded are trusted like git trusts them, unless they were written in the
+// same second as the index or later (racily clean), the rest are hashed
+fn worktree_dirty(worktree: &Path, index: &Index, deadline: Instant) -> Option<bool> {
+    for entry in &index.entries {
+        if Instant::now() >= deadline {
+            return None;
+        }
+        // an unresolved conflict is a change still to make
+        if entry.stage != 0 {
+            return Some(true);
+        }
+        if entry.skip_worktree || entry.mode == GITLINK_MODE {
+            continue;
+        }
+        let path = worktree.join(OsStr::from_bytes(&entry.path));
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            return Some(true);
+        };
+        if entry.mode != SYMLINK_MODE && (metadata.mode() & 0o100 != 0) != (entry.mode & 0o100 != 0) {
+            return Some(true);
+        }
+        if metadata.size() as u32 != entry.size {
+            return Some(true);
+        }
+        if metadata.mtime() as u32 == entry.mtime && entry.mtime < index.mtime {
+            continue;
+        }
+        let contents = if metadata.file_type().is_symlink() {
+            fs::read_link(&path).ok()?.as_os_str().as_bytes().to_vec()
+        } else {
+            fs::read(&path).ok()?
+        };
+        let mut blob = format!("blob {}\0", contents.len()).into_bytes();
+        blob.extend_from_slice(&contents);
+        if sha1(&blob) != entry.id {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+// every file of a tree with its mode and object, keyed by path
+fn flatten_tree(
+    store: &ObjectStore,
+    tree: ObjectId,
+    prefix: &[u8],
+    files: &mut HashMap<Vec<u8>, (u32, ObjectId)>,
+    deadline: Instant,
+) -> Option<()> {
+    if Instant::now() >= deadline {
+        return None;
+    }
+    let (kind, data) = store.read(&tree)?;
+    if kind != ObjectKind::Tree {
+        return None;
+    }
+    for entry in parse_tree(&data)? {
+        let mut path = prefix.to_vec();
+        path.extend_from_slice(&entry.name);
+        if entry.mode == TREE_MODE {
+            path.push(b'/');
+            flatten_tree(store, entry.id, &path, files, deadline)?;
+        } else {
+            files.insert(path, (entry.mode, entry.id));
+        }
+    }
+    Some(())
+}
+
+// whether the index differs from the HEAD commit. The cache-tree extension usually
+// answers that by itself, otherwise the whole HEAD tree is compared
+fn index_staged(store: &ObjectStore, index: &Index, head_tree: Option<ObjectId>, deadline: Instant) -> Option<bool> {
+    let Some(head_tree) = head_tree else {
+        // before the first commit anything in the index is staged
+        return Some(!index.entries.is_empty());
+    };
+    if let Some(root) = index.root_tree {
+        return Some(root != head_tree);
+    }
+    let mut files: HashMap<Vec<u8>, (u32, ObjectId)> = HashMap::new();
+    flatten_tree(store, head_tree, b"", &mut files, deadline)?;
+    if index.entries.len() != files.len() {
+        return Some(true);
+    }
+    let changed = index.entries.iter().any(|entry| {
+        entry.stage != 0 || files.get(&entry.path).is_none_or(|(mode, id)| *mode != entry.mode || *id != entry.id)
+    });
+    Some(changed)
+}
+
+fn details(worktree: &Path, git_dir: &Path, head: Option<ObjectId>, upstream: Option<ObjectId>, deadline: Instant) -> Details {
+    let store = ObjectStore::open(git_dir);
+    let mut details = Details {
+        dirty: None,
+        staged: None,
+        ahead_behind: None,
+    };
+    if let Some(index) = read_index(git_dir) {
+        let head_tree = head
+            .and_then(|id| store.read(&id))
+            .and_then(|(_, data)| parse_commit(&data))
+            .map(|commit| commit.tree);
+        details.staged = index_staged(&store, &index, head_tree, deadline);
+        details.dirty = worktree_dirty(worktree, &index, deadline);
+    }
+    if let (Some(head), Some(upstream)) = (head, upstream) {
+        details.ahead_behind = ahead_behind(&store, head, upstream, deadline);
+    }
+    details
+}
+
+// the slow parts run on their own thread so a huge repository or a slow disk costs the
+// prompt at most the timeout. The thread watches the same deadline and gives up soon after
+fn details_within(worktree: PathBuf, git_dir: PathBuf, head: Option<ObjectId>, upstream: Option<ObjectId>) -> Option<Details> {
+    let timeout = timeout();
+    if timeout.is_zero() {
+        return None;
+    }
+    let deadline = Instant::now() + timeout;
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name("git-status".to_string())
+        .spawn(move || {
+            let _ = sender.send(details(&worktree, &git_dir, head, upstream, deadline));
+        })
+        .ok()?;
+    receiver.recv_timeout(timeout).ok()
+}
+
+// ` (main *+ ↑1↓2|REBASE 1/3)` for the repository the shell is in, like git-prompt.sh,
+// empty outside one: `*` for unstaged changes, `+` for staged ones, then the commits
+// ahead of and behind the upstream and what is in progress
+pub fn git_segment() -> String {
+    let cwd = PathBuf::from(current_directory());
+    let Some((worktree, git_dir)) = find_repository(&cwd) else {
+        return String::new();
+    };
+    let (state, rebase_branch) = operation(&git_dir);
+    let head = resolve_ref(&git_dir, "HEAD");
+    let branch = read_trimmed(&git_dir.join("HEAD"))
+        .and_then(|contents| Some(contents.strip_prefix("ref:")?.trim().to_string()))
+        .or(rebase_branch)
+        .and_then(|name| Some(name.strip_prefix("refs/heads/")?.to_string()));
+    let label = match (&branch, head) {
+        (Some(branch), _) => branch.clone(),
+        (None, Some(head)) => format!("({}...)", &to_hex(&head)[..7]),
+        (None, None) => return String::new(),
+    };
+    let upstream = branch.as_deref().and_then(|branch| upstream(&git_dir, branch));
+
+    let mut segment = format!(" ({}", label);
+    // inside the .git directory there is no work tree to compare
+    if !cwd.starts_with(&git_dir)
+        && let Some(details) = details_within(worktree, git_dir, head, upstream)
+    {
+        let mut flags = String::new();
+        if details.dirty == Some(true) {
+            flags.push('*');
+        }
+        if details.staged == Some(true) {
+            flags.push('+');
+        }
+        if !flags.is_empty() {
+            segment.push(' ');
+            segment.push_str(&flags);
+        }
+        if let Some((ahead, behind)) = details.ahead_behind
+            && (ahead > 0 || behind > 0)
+        {
+            segment.push(' ');
+            if ahead > 0 {
+                segment.push_str(&format!("↑{}", ahead));
+            }
+            if behind > 0 {
+                segment.push_str(&format!("↓{}", behind));
+            }
+        }
+    }
+    if let Some(state) = state {
+        segment.push('|');
+        segment.push_str(&state);
+    }
+    segment.push(')');
+    segment
+}
@@ -0,0 +1,122 @@
+// shell pattern matching: `*`, `?`, `[abc]`, `[a-z]`, `[!x]`/`[^x]` and backslash escapes
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // where to resume after the last `*` if the rest fails to match
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let matched = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') if class_is_closed(pattern, p) => match_class(pattern, p, text[t]),
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(p + 2),
+            Some(&c) => (c == text[t]).then_some(p + 1),
+            None => None,
+        };
+
+        match matched {
+            Some(next) => {
+                p = next;
+                t += 1;
+            }
+            None => match backtrack {
+                Some((star, star_text)) => {
+                    p = star + 1;
+                    t = star_text + 1;
+                    backtrack = Some((star, star_text + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// an unterminated `[` is just a character
+fn class_is_closed(pattern: &[char], start: usize) -> bool {
+    let mut i = start + 1;
+    if matches!(pattern.get(i), Some('!' | '^')) {
+        i += 1;
+    }
+    // skip a leading literal `]`
+    i += 1;
+    pattern.get(i..).is_some_and(|rest| rest.contains(&']'))
+}
+
+// matches `c` against the bracket expression at `start`, returning the index after it
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<usize> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut found = false;
+    let mut first = true;
+    loop {
+        let current = *pattern.get(i)?;
+        // a `]` right after the opening bracket is a literal
+        if current == ']' && !first {
+            break;
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|end| *end != ']') {
+            if current <= c && c <= pattern[i + 2] {
+                found = true;
+            }
+            i += 3;
+        } else {
+            if current == c {
+                found = true;
+            }
+            i += 1;
+        }
+    }
+
+    if found != negated { Some(i + 1) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let cases = [
+            ("ls", "ls", true),
+            ("ls", "ls -l", false),
+            ("ls*", "ls -l", true),
+            ("*", "", true),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyyd", false),
+            ("*.rs", "src/main.rs", true),
+            ("?", "", false),
+            ("h?llo", "hallo", true),
+            ("[abc]x", "bx", true),
+            ("[abc]x", "dx", false),
+            ("[a-c]", "b", true),
+            ("[!a-c]", "b", false),
+            ("[^a-c]", "d", true),
+            ("[]]", "]", true),
+            ("[a-]", "-", true),
+            ("[ab", "[ab", true),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("é*", "été", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(glob_match(pattern, text), expected, "{:?} against {:?}", pattern, text);
+        }
+    }
+}
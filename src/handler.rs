@@ -1,14 +1,46 @@
-use std::fs::{OpenOptions, File};
-use pathsearch::find_executable_in_path;
-use std::path::{PathBuf, Path};
+use std::fs::{self, OpenOptions, File};
+use std::path::{Component, PathBuf, Path};
+use std::os::unix::fs::MetadataExt;
 use std::io::{self, Write};
 use std::process::Stdio;
-use std::env::{set_current_dir, home_dir};
-use std::process::{Command,Child};
+use std::env::{self, current_dir, set_current_dir};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, Child, ExitStatus};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use crate::command_cache::{find_command, forget_command, hash_command, hash_output};
+use crate::dir_stack::dirs_output;
+use crate::programmable::compgen_output;
+use crate::frecency::record_directory;
+use crate::shopt::shopt_enabled;
+use crate::spelling::correct_directory;
+
+pub const SHELL_COMMANDS: [&str; 15] = [
+    "echo", "type", "exit", "cd", "pwd", "history", "pushd", "popd", "dirs", "z", "shopt", "fc",
+    "complete", "compgen", "hash",
+];
+
+// exit status of the last command line, like bash's $?
+static LAST_STATUS: AtomicI32 = AtomicI32::new(0);
+
+pub fn last_status() -> i32 {
+    LAST_STATUS.load(Ordering::Relaxed)
+}
+
+pub fn set_last_status(status: i32) {
+    LAST_STATUS.store(status, Ordering::Relaxed);
+}
 
-pub const SHELL_COMMANDS: [&str; 5] = ["echo", "type", "exit", "cd", "pwd"];
+// a process killed by a signal reports 128 + the signal number
+fn record_exit_status(status: io::Result<ExitStatus>) {
+    let code = match status {
+        Ok(status) => status.code().or_else(|| status.signal().map(|signal| 128 + signal)).unwrap_or(1),
+        Err(_) => 1,
+    };
+    set_last_status(code);
+}
 
-pub fn echo_handler(args: &Vec<String>, redirect: bool, redirects: Vec<(String, String)>) {
+pub fn echo_handler(args: &[String], redirect: bool, redirects: Vec<(String, String)>) {
     if !redirect {
         for arg in args {
             print!("{} ", arg);
@@ -54,7 +86,7 @@ pub fn echo_handler(args: &Vec<String>, redirect: bool, redirects: Vec<(String,
     }
 }
 
-pub fn type_handler(args: &Vec<String>, redirect: bool, redirects: Vec<(String, String)>) {
+pub fn type_handler(args: &[String], redirect: bool, redirects: Vec<(String, String)>) {
     if !redirect {
         if args.is_empty() {
             println!("Not a valid command");
@@ -63,7 +95,7 @@ pub fn type_handler(args: &Vec<String>, redirect: bool, redirects: Vec<(String,
         for arg in args {
             if SHELL_COMMANDS.contains(&arg.as_str()) {
                 println!("{} is a shell builtin", arg);
-            } else if let Some(path) = find_executable_in_path(&arg) {
+            } else if let Some(path) = find_command(arg) {
                 println!("{} is {}", arg, path.display()); // this is third party way
             } else {
                 println!("{}: not found", arg);
@@ -109,18 +141,13 @@ pub fn type_handler(args: &Vec<String>, redirect: bool, redirects: Vec<(String,
         }
         for arg in args {
             let inbuilt = SHELL_COMMANDS.contains(&arg.as_str());
-            let executible = find_executable_in_path(&arg).is_some();
+            let executible = find_command(arg).is_some();
 
-            let path_result = find_executable_in_path(&arg);
+            let path_result = find_command(arg);
             let mut path = PathBuf::new();
 
-            match path_result {
-                Some(result) => {
-                    path = result;
-                }
-                None => {
-
-                }
+            if let Some(result) = path_result {
+                path = result;
             }
 
             if inbuilt | executible {
@@ -146,21 +173,52 @@ pub fn type_handler(args: &Vec<String>, redirect: bool, redirects: Vec<(String,
     }
 }
 
+// runs from the path the lookup found instead of searching $PATH again, argv[0] stays the
+// name that was typed
+fn hashed_command(path: &Path, name: &str) -> Command {
+    let mut command = Command::new(path);
+    command.arg0(name);
+    command
+}
+
+// starts `command`. When the hashed path no longer runs it is forgotten and the error is
+// reported like bash does: 127 when the file is gone, 126 when it cannot be executed
+fn spawn_hashed(command: &mut Command, name: &str) -> Option<Child> {
+    match command.spawn() {
+        Ok(child) => Some(child),
+        Err(e) => {
+            forget_command(name);
+            set_last_status(if e.kind() == io::ErrorKind::NotFound { 127 } else { 126 });
+            println!("{}: {}", name, describe_io_error(&e));
+            None
+        }
+    }
+}
+
 pub fn pwd_handler(
-    args: &Vec<String>,
+    args: &[String],
     command: &str,
     redirect: bool,
     redirects: Vec<(String, String)>,
 ) {
+    builtin_output_handler(pwd_output(args, command), redirect, redirects);
+}
+
+// prints a builtin's result, Ok goes to the output redirect and Err to the error redirect
+pub fn builtin_output_handler(
+    result: Result<String, String>,
+    redirect: bool,
+    redirects: Vec<(String, String)>,
+) {
+    set_last_status(if result.is_ok() { 0 } else { 1 });
+    // builtins with nothing to say return an empty Ok
+    if matches!(&result, Ok(msg) if msg.is_empty()) {
+        return;
+    }
     if !redirect {
-        if !args.is_empty() {
-            println!("{}: Invalid arguments provided", command.trim());
-        } else {
-            let path_result: Result<std::path::PathBuf, io::Error> = std::env::current_dir();
-            match path_result {
-                Err(e) => println!("Not Found Error: {}", e),
-                Ok(path_buf) => println!("{}", path_buf.display()),
-            };
+        match result {
+            Ok(msg) => println!("{}", msg),
+            Err(msg) => println!("{}", msg),
         }
     } else {
         let (write_location, write_type) = &redirects[redirects.len() - 1];
@@ -193,89 +251,264 @@ pub fn pwd_handler(
             }
         }
 
-        if !args.is_empty() {
-            let msg = format!("{}: Invalid arguments provided", command.trim());
-
-            if write_type.trim().contains("error") {
-                let write = writeln!(file, "{}", msg);
-                if write.is_err(){
-                    println!("Sorry could not write to file");
-                }
-            } else {
-                println!("{}: Invalid arguments provided", command.trim());
+        let (msg, redirected) = match result {
+            Ok(msg) => (msg, write_type.trim().contains("output")),
+            Err(msg) => (msg, write_type.trim().contains("error")),
+        };
+        if redirected {
+            let write = writeln!(file, "{}", msg);
+            if write.is_err(){
+                println!("Sorry could not write to file");
             }
         } else {
-            let msg = if let Ok(path) = std::env::current_dir() {
-                format!("{}", path.display())
-            } else {
-                format!("Sorry could not find current directory")
-            };
-            if std::env::current_dir().is_ok() {
-                if write_type.trim().contains("output") {
-                    let write = writeln!(file, "{}", msg);
-                    if write.is_err(){
-                        println!("Sorry could not write to file");
-                    }
-                } else {
-                    println!("{}", msg);
-                }
-            } else {
-                if write_type.trim().contains("error") {
-                    let write = writeln!(file, "{}", msg);
-                    if write.is_err(){
-                        println!("Sorry could not write to file");
-                    }
-                } else {
-                    println!("{}", msg);
-                }
+            println!("{}", msg);
+        }
+    }
+}
+
+// -L (the default) prints the logical $PWD, -P the path with all symlinks resolved
+pub fn pwd_output(args: &[String], command: &str) -> Result<String, String> {
+    let mut physical = false;
+    for arg in args {
+        match arg.as_str() {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            "--" => {}
+            _ if arg.starts_with('-') => {
+                return Err(format!("{}: {}: invalid option", command.trim(), arg));
+            }
+            _ => return Err(format!("{}: too many arguments", command.trim())),
+        }
+    }
+
+    if physical {
+        match current_dir() {
+            Ok(path) => Ok(path.display().to_string()),
+            Err(e) => Err(format!("{}: {}", command.trim(), e)),
+        }
+    } else {
+        logical_pwd().ok_or_else(|| format!("{}: could not find current directory", command.trim()))
+    }
+}
+
+// $PWD is trusted only while it is absolute, free of `.`/`..` and still names the cwd
+pub fn logical_pwd() -> Option<String> {
+    if let Ok(pwd) = env::var("PWD") {
+        let path = Path::new(&pwd);
+        let is_clean = path.is_absolute()
+            && path
+                .components()
+                .all(|component| !matches!(component, Component::CurDir | Component::ParentDir));
+        if is_clean
+            && let (Ok(logical), Ok(physical)) = (fs::metadata(path), fs::metadata("."))
+            && logical.dev() == physical.dev()
+            && logical.ino() == physical.ino()
+        {
+            return Some(pwd);
+        }
+    }
+    current_dir().ok().map(|path| path.display().to_string())
+}
+
+pub fn init_pwd() {
+    if let Some(pwd) = logical_pwd() {
+        set_shell_var("PWD", &pwd);
+    }
+}
+
+// resolves `.` and `..` textually, so `..` leaves a symlink the way it was entered
+pub fn normalize_logical(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
             }
+            other => normalized.push(other),
         }
     }
+    normalized
 }
 
-pub fn cd_handler(args: &Vec<String>, command: &str) {
-    if args.len() >= 2 {
+pub fn describe_io_error(e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::NotFound => "No such file or directory".to_string(),
+        io::ErrorKind::NotADirectory => "Not a directory".to_string(),
+        io::ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        _ => e.to_string(),
+    }
+}
+
+pub fn cd_handler(args: &[String], command: &str) {
+    // a failure until the directory actually changes
+    set_last_status(1);
+    let mut physical = false;
+    let mut operands: Vec<&str> = Vec::new();
+    let mut options_done = false;
+    for arg in args {
+        match arg.as_str() {
+            "-L" if !options_done => physical = false,
+            "-P" if !options_done => physical = true,
+            "--" if !options_done => options_done = true,
+            "-" => operands.push(arg),
+            _ if arg.starts_with('-') && !options_done => {
+                println!("{}: {}: invalid option", command.trim(), arg);
+                return;
+            }
+            _ => {
+                options_done = true;
+                operands.push(arg);
+            }
+        }
+    }
+
+    if operands.len() >= 2 {
         println!("{}: Too many arguments", command.trim());
         return;
-    } else {
-        match args[0].as_str() {
-            "" => {
-                let root = Path::new("/");
-                let _result = set_current_dir(root);
+    }
+
+    let mut print_directory = false;
+    let target = match operands.first().copied() {
+        None => match env::var("HOME") {
+            Ok(home) => home,
+            Err(_) => {
+                println!("{}: HOME not set", command.trim());
                 return;
             }
-            "~" => {
-                let path = home_dir().expect("sorry cannot find your home dir");
-                let _result = set_current_dir(path);
+        },
+        Some("-") => match env::var("OLDPWD") {
+            Ok(old_directory) => {
+                print_directory = true;
+                old_directory
+            }
+            Err(_) => {
+                println!("{}: OLDPWD not set", command.trim());
                 return;
             }
-            _ => {
-                let path = Path::new(&args[0]);
-                let is_path_correct = path
-                    .try_exists()
-                    .expect("Can't check existence of provided file");
-                if is_path_correct {
-                    let _result = set_current_dir(path);
-                } else {
-                    println!("{}: {}: No such file or directory", command.trim(), args[0]);
+        },
+        Some(directory) => directory.to_string(),
+    };
+
+    let path = match search_cdpath(&target) {
+        Some(found) => {
+            print_directory = true;
+            found
+        }
+        None => PathBuf::from(&target),
+    };
+
+    match change_directory(&path, physical) {
+        Ok(new_directory) => {
+            set_last_status(0);
+            if print_directory {
+                println!("{}", new_directory);
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => match correct_directory(&target) {
+            Some(corrected) if shopt_enabled("cdspell") => {
+                // like bash, the corrected name is echoed before moving there
+                println!("{}", corrected.display());
+                match change_directory(&corrected, physical) {
+                    Ok(_) => set_last_status(0),
+                    Err(e) => println!("{}: {}: {}", command.trim(), corrected.display(), describe_io_error(&e)),
                 }
-                return;
             }
+            Some(corrected) => {
+                println!("{}: {}: {}", command.trim(), target, describe_io_error(&e));
+                println!("{}: did you mean '{}'?", command.trim(), corrected.display());
+            }
+            None => {
+                println!("{}: {}: {}", command.trim(), target, describe_io_error(&e));
+            }
+        },
+        Err(e) => {
+            println!("{}: {}: {}", command.trim(), target, describe_io_error(&e));
+        }
+    }
+}
+
+// moves the shell into `path` and keeps PWD/OLDPWD in sync, returns the new $PWD
+pub fn change_directory(path: &Path, physical: bool) -> io::Result<String> {
+    let old_directory = logical_pwd();
+
+    // with no directory to start from (PWD unset and the current one gone) a relative
+    // path can only be resolved by the kernel, joining it to "" would make PWD relative
+    let new_directory = match old_directory.as_deref() {
+        Some(base) if !physical => {
+            let logical = normalize_logical(&Path::new(base).join(path));
+            match set_current_dir(&logical) {
+                Ok(_) => logical.display().to_string(),
+                Err(_) => {
+                    // bash falls back to the path as given when the logical one cannot be entered
+                    set_current_dir(path)?;
+                    current_dir()?.display().to_string()
+                }
+            }
+        }
+        _ => {
+            set_current_dir(path)?;
+            current_dir()?.display().to_string()
+        }
+    };
+
+    if let Some(old_directory) = old_directory {
+        set_shell_var("OLDPWD", &old_directory);
+    }
+    set_shell_var("PWD", &new_directory);
+    record_directory(&new_directory);
+    Ok(new_directory)
+}
+
+// relative names that do not start with `.` or `..` are looked up in every CDPATH entry
+fn search_cdpath(target: &str) -> Option<PathBuf> {
+    if target.is_empty()
+        || target.starts_with('/')
+        || target == "."
+        || target == ".."
+        || target.starts_with("./")
+        || target.starts_with("../")
+    {
+        return None;
+    }
+
+    let cdpath = env::var_os("CDPATH")?;
+    for entry in env::split_paths(&cdpath) {
+        // an empty entry means the current directory, which needs no announcement
+        if entry.as_os_str().is_empty() {
+            if Path::new(target).is_dir() {
+                return None;
+            }
+            continue;
         }
+        let candidate = entry.join(target);
+        if candidate.is_dir() {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+pub fn set_shell_var(key: &str, value: &str) {
+    // SAFETY: builtins only run on the main thread, nothing reads the environment concurrently
+    unsafe {
+        env::set_var(key, value);
     }
 }
 
 pub fn general_handler(
-    args: &Vec<String>,
+    args: &[String],
     command: &str,
     redirect: bool,
     redirects: Vec<(String, String)>,
 ) {
     if !redirect {
-        if let Some(_path) = find_executable_in_path(&command.trim()) {
-            let mut process = Command::new(command.trim()).args(args).spawn().unwrap();
-            let _status = process.wait().unwrap();
+        if let Some(path) = hash_command(command.trim()) {
+            if let Some(mut process) = spawn_hashed(hashed_command(&path, command.trim()).args(args), command.trim()) {
+                record_exit_status(process.wait());
+            }
         } else {
+            set_last_status(127);
             println!("{}: command not found", command.trim());
         }
     } else {
@@ -309,26 +542,19 @@ pub fn general_handler(
             }
         }
 
-        if let Some(_path) = find_executable_in_path(&command.trim()) {
+        if let Some(path) = hash_command(command.trim()) {
+            let mut process = hashed_command(&path, command.trim());
+            process.args(args);
             if write_type.trim().contains("output") {
-                let mut process = Command::new(command.trim())
-                    .args(args)
-                    .stdout(Stdio::from(file))
-                    .spawn()
-                    .unwrap();
-                let _status = process.wait().unwrap();
+                process.stdout(Stdio::from(file));
             } else if write_type.trim().contains("error") {
-                let mut process = Command::new(command.trim())
-                    .args(args)
-                    .stderr(Stdio::from(file))
-                    .spawn()
-                    .unwrap();
-                let _status = process.wait().unwrap();
-            } else {
-                let mut process = Command::new(command.trim()).args(args).spawn().unwrap();
-                let _status = process.wait().unwrap();
+                process.stderr(Stdio::from(file));
+            }
+            if let Some(mut process) = spawn_hashed(&mut process, command.trim()) {
+                record_exit_status(process.wait());
             }
         } else {
+            set_last_status(127);
             if write_type.trim().contains("error") {
                 let write = writeln!(file, "{}: command not found", command.trim());
                 if write.is_err(){
@@ -338,7 +564,6 @@ pub fn general_handler(
             println!("{}: command not found", command.trim());
         }
     }
-    return;
 }
 
 pub fn redirect_handler(redirects: Vec<(String, String)>) {
@@ -356,7 +581,6 @@ pub fn redirect_handler(redirects: Vec<(String, String)>) {
             }
             "append_output" | "append_error" => {
                 let file_result = OpenOptions::new()
-                    .write(true)
                     .create(true)
                     .append(true)
                     .open(path);
@@ -371,8 +595,8 @@ pub fn redirect_handler(redirects: Vec<(String, String)>) {
     }
 }
 
-pub fn execute_pipeline(all_commands: &Vec<Vec<String>>, redirect: bool, redirects: Vec<(String, String)>, last_entry: &mut usize) {
-    use std::process::{Command, Stdio};
+pub fn execute_pipeline(all_commands: &[Vec<String>], redirect: bool, redirects: Vec<(String, String)>, _last_entry: &mut usize) {
+    use std::process::Stdio;
     use std::io::Write;
     
     if all_commands.is_empty() {
@@ -409,9 +633,30 @@ pub fn execute_pipeline(all_commands: &Vec<Vec<String>>, redirect: bool, redirec
                     output = line.into_bytes();
                 }
                 "pwd" => {
-                    if let Ok(path) = std::env::current_dir() {
-                        let line = format!("{}\n", path.display());
-                        output = line.into_bytes();
+                    match pwd_output(args, command) {
+                        Ok(path) => output = format!("{}\n", path).into_bytes(),
+                        Err(msg) => println!("{}", msg),
+                    }
+                }
+                "compgen" => {
+                    match compgen_output(args, command) {
+                        Ok(lines) if !lines.is_empty() => output = format!("{}\n", lines).into_bytes(),
+                        Ok(_) => {}
+                        Err(msg) => println!("{}", msg),
+                    }
+                }
+                "hash" => {
+                    match hash_output(args, command) {
+                        Ok(lines) if !lines.is_empty() => output = format!("{}\n", lines).into_bytes(),
+                        Ok(_) => {}
+                        Err(msg) => println!("{}", msg),
+                    }
+                }
+                "dirs" => {
+                    match dirs_output(args, command) {
+                        Ok(lines) if !lines.is_empty() => output = format!("{}\n", lines).into_bytes(),
+                        Ok(_) => {}
+                        Err(msg) => println!("{}", msg),
                     }
                 }
                 "type" => {
@@ -419,7 +664,7 @@ pub fn execute_pipeline(all_commands: &Vec<Vec<String>>, redirect: bool, redirec
                         let arg = &args[0];
                         let line = if SHELL_COMMANDS.contains(&arg.as_str()) {
                             format!("{} is a shell builtin\n", arg)
-                        } else if let Some(path) = find_executable_in_path(&arg) {
+                        } else if let Some(path) = find_command(arg) {
                             format!("{} is {}\n", arg, path.display())
                         } else {
                             format!("{}: not found\n", arg)
@@ -430,7 +675,7 @@ pub fn execute_pipeline(all_commands: &Vec<Vec<String>>, redirect: bool, redirec
 
                 // For 'cd' and 'exit', dont know how to handle in pipeline, so just skip for now 
 
-                "cd" | "exit" => {
+                "cd" | "pushd" | "popd" | "exit" => {
                     println!("{}: dont know how to handle in pipeline sorry", command);
                     continue;
                 }
@@ -467,14 +712,18 @@ pub fn execute_pipeline(all_commands: &Vec<Vec<String>>, redirect: bool, redirec
             continue;
         }
 
-        if let Some(_path) = find_executable_in_path(&command.trim()) {
-            let mut cmd = Command::new(command.trim());
+        if let Some(path) = hash_command(command.trim()) {
+            let mut cmd = hashed_command(&path, command.trim());
             cmd.args(args);
 
             if let Some(prev_out) = prev_stdout.take() {
                 cmd.stdin(prev_out);
             } else if let Some(builtin_out) = builtin_output.take() {
-                let mut child = cmd.stdin(Stdio::piped()).spawn().unwrap();
+                let Some(mut child) = spawn_hashed(cmd.stdin(Stdio::piped()), command.trim()) else {
+                    // the commands already started are still waited for
+                    processes.into_iter().for_each(|mut process| drop(process.wait()));
+                    return;
+                };
                 if let Some(stdin) = child.stdin.take() {
                     let mut stdin = stdin;
                     stdin.write_all(&builtin_out).unwrap();
@@ -486,41 +735,39 @@ pub fn execute_pipeline(all_commands: &Vec<Vec<String>>, redirect: bool, redirec
 
             if !is_last {
                 cmd.stdout(Stdio::piped());
-            } else if redirect {
-                if let Some((write_location, write_type)) = redirects.last() {
-                    if write_type.trim().contains("output") {
-                        let file = std::fs::OpenOptions::new()
-                            .create(true)
-                            .write(true)
-                            .truncate(!write_type.trim().contains("append"))
-                            .append(write_type.trim().contains("append"))
-                            .open(write_location);
-                        if let Ok(f) = file {
-                            cmd.stdout(f);
-                        }
-                    }
+            } else if redirect
+                && let Some((write_location, write_type)) = redirects.last()
+                && write_type.trim().contains("output")
+            {
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(!write_type.trim().contains("append"))
+                    .append(write_type.trim().contains("append"))
+                    .open(write_location);
+                if let Ok(f) = file {
+                    cmd.stdout(f);
                 }
             }
             
-            match cmd.spawn() {
-                Ok(mut child) => {
-                    if !is_last {
-                        prev_stdout = child.stdout.take().map(Stdio::from);
-                    }
-                    processes.push(child);
-                }
-                Err(e) => {
-                    println!("Failed to execute {}: {}", command, e);
-                    return;
-                }
+            let Some(mut child) = spawn_hashed(&mut cmd, command.trim()) else {
+                // the commands already started are still waited for
+                processes.into_iter().for_each(|mut process| drop(process.wait()));
+                return;
+            };
+            if !is_last {
+                prev_stdout = child.stdout.take().map(Stdio::from);
             }
+            processes.push(child);
         } else {
+            set_last_status(127);
             println!("{}: command not found", command.trim());
             return;
         }
     }
 
+    // the pipeline's status is the one of its last command
     for mut process in processes {
-        let _ = process.wait();
+        record_exit_status(process.wait());
     }
 }
\ No newline at end of file
@@ -0,0 +1,223 @@
+use std::env;
+use std::path::Path;
+
+use crate::command_cache::find_command;
+use crate::handler::SHELL_COMMANDS;
+use crate::input_parser::{expand_tilde, tokenize, TokenKind, Tokens, SUPPORTED_OPERATORS, SUPPORTED_REDIRECTS};
+use crate::shopt::shopt_enabled;
+
+// SGR codes for each part of the line, changed with SHELLY_COLORS like GREP_COLORS:
+// `command=1;32:error=31`
+const DEFAULT_COLORS: [(&str, &str); 10] = [
+    ("command", "32"),
+    ("error", "31"),
+    ("string", "33"),
+    ("redirect", "35"),
+    ("operator", "36"),
+    ("variable", "34"),
+    ("comment", "90"),
+    ("path", "4"),
+    ("match", "7"),
+    ("hint", "2"),
+];
+
+pub struct Colors {
+    codes: Vec<(&'static str, String)>,
+}
+
+impl Colors {
+    // None when NO_COLOR is set, see https://no-color.org
+    pub fn from_env() -> Option<Self> {
+        if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return None;
+        }
+        let mut codes: Vec<(&'static str, String)> =
+            DEFAULT_COLORS.iter().map(|(name, code)| (*name, code.to_string())).collect();
+        if let Ok(spec) = env::var("SHELLY_COLORS") {
+            for entry in spec.split(':') {
+                let Some((name, code)) = entry.split_once('=') else {
+                    continue;
+                };
+                // anything but digits and `;` would break the escape sequence
+                if !code.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                    continue;
+                }
+                if let Some(slot) = codes.iter_mut().find(|(known, _)| *known == name) {
+                    slot.1 = code.to_string();
+                }
+            }
+        }
+        Some(Colors { codes })
+    }
+
+    pub fn code(&self, name: &str) -> &str {
+        self.codes
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map_or("", |(_, code)| code.as_str())
+    }
+
+    pub fn paint(&self, name: &str, text: &str) -> String {
+        match self.code(name) {
+            "" => text.to_string(),
+            code => format!("\x1b[{}m{}\x1b[0m", code, text),
+        }
+    }
+}
+
+// builtins, commands on $PATH and, with autocd, directories. Reserved words are not,
+// the executor has no compound commands
+fn is_runnable(name: &str) -> bool {
+    !name.is_empty()
+        && (SHELL_COMMANDS.contains(&name)
+            || find_command(name).is_some()
+            || (shopt_enabled("autocd") && Path::new(name).is_dir()))
+}
+
+fn path_exists(text: &str) -> bool {
+    if text.is_empty() {
+        return false;
+    }
+    if let Some(rest) = text.strip_prefix('~') {
+        let (user, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if let Some(home) = expand_tilde(user) {
+            return Path::new(&home).join(path).exists();
+        }
+    }
+    Path::new(text).exists()
+}
+
+fn matching_bracket(c: char) -> Option<(char, bool)> {
+    match c {
+        '(' => Some((')', true)),
+        '[' => Some((']', true)),
+        '{' => Some(('}', true)),
+        ')' => Some(('(', false)),
+        ']' => Some(('[', false)),
+        '}' => Some(('{', false)),
+        _ => None,
+    }
+}
+
+// the bracket at `at` and its partner
+fn bracket_pair(line: &str, at: usize) -> Option<(usize, usize)> {
+    let c = line[at..].chars().next()?;
+    let (partner, forward) = matching_bracket(c)?;
+    let mut depth = 0;
+    if forward {
+        for (i, d) in line[at..].char_indices() {
+            if d == c {
+                depth += 1;
+            } else if d == partner {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((at, at + i));
+                }
+            }
+        }
+    } else {
+        for (i, d) in line[..=at].char_indices().rev() {
+            if d == c {
+                depth += 1;
+            } else if d == partner {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((i, at));
+                }
+            }
+        }
+    }
+    None
+}
+
+// the closed quote starting or ending at `at`
+fn quote_pair(tokens: &Tokens, line: &str, at: usize) -> Option<(usize, usize)> {
+    tokens
+        .tokens
+        .iter()
+        .flat_map(|token| token.quoted.iter())
+        .filter(|(start, end)| *end - *start >= 2 && line[*start..].chars().next() == line[..*end].chars().last())
+        .find(|(start, end)| *start == at || *end - 1 == at)
+        .copied()
+}
+
+// the pair under the cursor or just before it, like readline's blink-matching-paren
+fn matching_pair(tokens: &Tokens, line: &str, pos: usize) -> Option<(usize, usize)> {
+    let before = line[..pos].char_indices().last().map(|(i, _)| i);
+    [Some(pos).filter(|at| *at < line.len()), before]
+        .into_iter()
+        .flatten()
+        .find_map(|at| quote_pair(tokens, line, at).or_else(|| bracket_pair(line, at)))
+}
+
+// the line with SGR codes around each part, None when nothing needs colour
+pub fn highlight_line(line: &str, pos: usize, show_match: bool) -> Option<String> {
+    let colors = Colors::from_env()?;
+    if line.is_empty() {
+        return None;
+    }
+    let tokens = tokenize(line);
+    // the codes for each byte, joined with `;`
+    let mut styles: Vec<Vec<&str>> = vec![Vec::new(); line.len()];
+    let mut style = |start: usize, end: usize, name: &str| {
+        let code = colors.code(name);
+        if code.is_empty() {
+            return;
+        }
+        for slot in &mut styles[start..end.min(line.len())] {
+            slot.push(code);
+        }
+    };
+
+    for token in &tokens.tokens {
+        match token.kind {
+            // painted the way input_parser will take them, what it refuses is an error
+            TokenKind::Operator if SUPPORTED_OPERATORS.contains(&&line[token.start..token.end]) => {
+                style(token.start, token.end, "operator")
+            }
+            TokenKind::Redirect if SUPPORTED_REDIRECTS.contains(&&line[token.start..token.end]) => {
+                style(token.start, token.end, "redirect")
+            }
+            TokenKind::Operator | TokenKind::Redirect => style(token.start, token.end, "error"),
+            TokenKind::Comment => style(token.start, token.end, "comment"),
+            TokenKind::Heredoc => style(token.start, token.end, "string"),
+            TokenKind::Word => {
+                if token.command_position {
+                    let name = if is_runnable(&token.text) { "command" } else { "error" };
+                    style(token.start, token.end, name);
+                } else if path_exists(&token.text) {
+                    style(token.start, token.end, "path");
+                }
+                for (start, end) in &token.quoted {
+                    style(*start, *end, "string");
+                }
+                for (start, end) in &token.variables {
+                    style(*start, *end, "variable");
+                }
+            }
+        }
+    }
+    if show_match && let Some((open, close)) = matching_pair(&tokens, line, pos) {
+        style(open, open + 1, "match");
+        style(close, close + 1, "match");
+    }
+
+    let mut painted = String::with_capacity(line.len() * 2);
+    let mut current: &[&str] = &[];
+    for (i, c) in line.char_indices() {
+        if styles[i].as_slice() != current {
+            if !current.is_empty() {
+                painted.push_str("\x1b[0m");
+            }
+            current = styles[i].as_slice();
+            if !current.is_empty() {
+                painted.push_str(&format!("\x1b[{}m", current.join(";")));
+            }
+        }
+        painted.push(c);
+    }
+    if !current.is_empty() {
+        painted.push_str("\x1b[0m");
+    }
+    Some(painted)
+}
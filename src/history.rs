@@ -0,0 +1,749 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use rustyline::config::Builder;
+use rustyline::history::{History, MemHistory, SearchDirection, SearchResult};
+use rustyline::{Config, Editor};
+
+use crate::MyHelper;
+use crate::glob::glob_match;
+use crate::handler::{builtin_output_handler, describe_io_error, set_shell_var};
+use crate::history_expansion::expand_history;
+use crate::history_store::{is_store_option, store_output};
+use crate::time_format::{format_local_time, now_epoch};
+use crate::xdg::{lock_file, replace_file, state_file};
+
+// how many entries of the history file this session has already read or written, for `history -n`
+static HISTFILE_LINES: AtomicUsize = AtomicUsize::new(0);
+
+// whether add_history kept the line being run, `history -s`, `history -p` and `fc` only
+// take it back out of the list when it is actually there
+static LINE_ADDED: AtomicBool = AtomicBool::new(false);
+
+// an entry and the time it was run, if known
+pub type TimedEntry = (String, Option<i64>);
+
+// rustyline's in-memory list plus the time of every entry, kept index for index
+pub struct ShellHistory {
+    entries: MemHistory,
+    times: VecDeque<Option<i64>>,
+    ignore_dups: bool,
+    ignore_space: bool,
+}
+
+impl ShellHistory {
+    pub fn with_config(config: &Config) -> Self {
+        Self {
+            entries: MemHistory::with_config(config),
+            times: VecDeque::new(),
+            ignore_dups: config.history_duplicates() == rustyline::config::HistoryDuplicates::IgnoreConsecutive,
+            ignore_space: config.history_ignore_space(),
+        }
+    }
+
+    pub fn iter(&self) -> std::collections::vec_deque::Iter<'_, String> {
+        (&self.entries).into_iter()
+    }
+
+    pub fn time(&self, index: usize) -> Option<i64> {
+        self.times.get(index).copied().flatten()
+    }
+
+    pub fn timed_entries(&self) -> Vec<TimedEntry> {
+        self.iter().cloned().zip(self.times.iter().copied()).collect()
+    }
+
+    pub fn add_with_time(&mut self, line: &str, time: Option<i64>) -> rustyline::Result<bool> {
+        let length = self.entries.len();
+        let added = self.entries.add(line)?;
+        if added {
+            // a full list drops its oldest entry to make room
+            if self.entries.len() == length {
+                self.times.pop_front();
+            }
+            self.times.push_back(time);
+        }
+        Ok(added)
+    }
+
+    // the list is rebuilt as given, without HISTCONTROL dropping anything on the way
+    pub fn replace(&mut self, entries: Vec<TimedEntry>) {
+        let _ = self.entries.clear();
+        self.times.clear();
+        let _ = self.entries.ignore_dups(false);
+        self.entries.ignore_space(false);
+        for (line, time) in entries {
+            let _ = self.add_with_time(&line, time);
+        }
+        let _ = self.entries.ignore_dups(self.ignore_dups);
+        self.entries.ignore_space(self.ignore_space);
+    }
+}
+
+impl History for ShellHistory {
+    fn get(&self, index: usize, dir: SearchDirection) -> rustyline::Result<Option<SearchResult<'_>>> {
+        self.entries.get(index, dir)
+    }
+
+    fn add(&mut self, line: &str) -> rustyline::Result<bool> {
+        self.add_with_time(line, Some(now_epoch()))
+    }
+
+    fn add_owned(&mut self, line: String) -> rustyline::Result<bool> {
+        self.add_with_time(&line, Some(now_epoch()))
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn set_max_len(&mut self, len: usize) -> rustyline::Result<()> {
+        self.entries.set_max_len(len)?;
+        while self.times.len() > self.entries.len() {
+            self.times.pop_front();
+        }
+        Ok(())
+    }
+
+    fn ignore_dups(&mut self, yes: bool) -> rustyline::Result<()> {
+        self.ignore_dups = yes;
+        self.entries.ignore_dups(yes)
+    }
+
+    fn ignore_space(&mut self, yes: bool) {
+        self.ignore_space = yes;
+        self.entries.ignore_space(yes);
+    }
+
+    fn save(&mut self, path: &Path) -> rustyline::Result<()> {
+        let mut file = OpenOptions::new().write(true).truncate(true).create(true).open(path)?;
+        write_entries(&mut file, &self.timed_entries(), false)?;
+        Ok(())
+    }
+
+    fn append(&mut self, path: &Path) -> rustyline::Result<()> {
+        let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+        write_entries(&mut file, &self.timed_entries(), true)?;
+        Ok(())
+    }
+
+    fn load(&mut self, path: &Path) -> rustyline::Result<()> {
+        for (line, time) in read_history_entries(path)? {
+            self.add_with_time(&line, time)?;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) -> rustyline::Result<()> {
+        self.times.clear();
+        self.entries.clear()
+    }
+
+    fn search(&self, term: &str, start: usize, dir: SearchDirection) -> rustyline::Result<Option<SearchResult<'_>>> {
+        self.entries.search(term, start, dir)
+    }
+
+    fn starts_with(&self, term: &str, start: usize, dir: SearchDirection) -> rustyline::Result<Option<SearchResult<'_>>> {
+        self.entries.starts_with(term, start, dir)
+    }
+}
+
+// a `#<epoch>` line gives the time of the entry under it, like bash writes them;
+// files without any still load line by line
+fn parse_history(contents: &str) -> Vec<TimedEntry> {
+    let mut entries: Vec<TimedEntry> = Vec::new();
+    let mut time: Option<i64> = None;
+    // after a timestamp every line up to the next one is the same multi-line entry, like
+    // bash with lithist. Files without timestamps have one entry per line
+    let mut continues_entry = false;
+    for (i, line) in contents.lines().enumerate() {
+        // the header rustyline puts on files it saved
+        if i == 0 && line == "#V2" {
+            continue;
+        }
+        if let Some(epoch) = line.strip_prefix('#')
+            && !epoch.is_empty()
+            && epoch.chars().all(|c| c.is_ascii_digit())
+        {
+            time = epoch.parse().ok();
+            continues_entry = false;
+            continue;
+        }
+        if continues_entry && let Some((entry, _)) = entries.last_mut() {
+            entry.push('\n');
+            entry.push_str(line);
+            continue;
+        }
+        continues_entry = time.is_some();
+        entries.push((line.to_string(), time.take()));
+    }
+    entries
+}
+
+// once an entry has a timestamp every later one needs its own, or it would read back as
+// more lines of the entry above it. Appending assumes the file may already end in a timed
+// entry. An entry without a time takes the one of the entry before it
+fn write_entries(file: &mut impl Write, entries: &[TimedEntry], appending: bool) -> std::io::Result<()> {
+    let mut needs_time = appending;
+    let mut last_time: Option<i64> = None;
+    for (line, time) in entries {
+        let time = time.or_else(|| needs_time.then(|| last_time.unwrap_or_else(now_epoch)));
+        if let Some(time) = time {
+            writeln!(file, "#{}", time)?;
+            needs_time = true;
+            last_time = Some(time);
+        }
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn read_history_entries(path: &Path) -> std::io::Result<Vec<TimedEntry>> {
+    Ok(parse_history(&fs::read_to_string(path)?))
+}
+
+// bash's default for both HISTSIZE and HISTFILESIZE
+const DEFAULT_HISTORY_SIZE: usize = 500;
+
+// a negative or non-numeric value means no limit, an unset one the bash default
+fn size_variable(name: &str) -> Option<Option<usize>> {
+    let value = env::var(name).ok()?;
+    Some(value.trim().parse::<usize>().ok())
+}
+
+fn history_size() -> Option<usize> {
+    size_variable("HISTSIZE").unwrap_or(Some(DEFAULT_HISTORY_SIZE))
+}
+
+fn history_file_size() -> Option<usize> {
+    size_variable("HISTFILESIZE").unwrap_or_else(history_size)
+}
+
+fn history_control() -> Vec<String> {
+    env::var("HISTCONTROL")
+        .unwrap_or_default()
+        .split(':')
+        .map(String::from)
+        .collect()
+}
+
+// HISTSIZE and the ignorespace/ignoredups parts of HISTCONTROL are enforced by rustyline itself
+pub fn apply_history_settings(builder: Builder) -> Builder {
+    let control = history_control();
+    let has = |value: &str| control.iter().any(|entry| entry == value || entry == "ignoreboth");
+
+    let mut builder = builder.history_ignore_space(has("ignorespace"));
+    if let Ok(updated) = builder.clone().history_ignore_dups(has("ignoredups")) {
+        builder = updated;
+    }
+    if let Ok(updated) = builder.clone().max_history_size(history_size().unwrap_or(usize::MAX)) {
+        builder = updated;
+    }
+    builder
+}
+
+fn is_ignored(line: &str, previous: Option<&String>) -> bool {
+    env::var("HISTIGNORE").is_ok_and(|patterns| matches_ignore_patterns(&patterns, line, previous))
+}
+
+// HISTIGNORE is a colon separated list of patterns, `&` stands for the previous entry
+fn matches_ignore_patterns(patterns: &str, line: &str, previous: Option<&String>) -> bool {
+    let mut pattern = String::new();
+    let mut chars = patterns.chars().peekable();
+    let mut all_patterns: Vec<String> = Vec::new();
+    while let Some(character) = chars.next() {
+        match character {
+            '\\' if chars.peek() == Some(&':') => {
+                pattern.push(':');
+                chars.next();
+            }
+            ':' => all_patterns.push(std::mem::take(&mut pattern)),
+            _ => pattern.push(character),
+        }
+    }
+    all_patterns.push(pattern);
+
+    all_patterns.iter().filter(|pattern| !pattern.is_empty()).any(|pattern| {
+        if pattern == "&" {
+            previous.is_some_and(|previous| previous == line)
+        } else {
+            glob_match(pattern, line)
+        }
+    })
+}
+
+// the one place interactive lines enter the list, so HISTCONTROL and HISTIGNORE apply.
+// False when they turned the line away; a repeat ignoredups drops is still kept
+pub fn add_history(readline: &mut Editor<MyHelper, ShellHistory>, line: &str, last_entry: &mut usize) -> bool {
+    LINE_ADDED.store(false, Ordering::Relaxed);
+    if line.trim().is_empty() || is_ignored(line, readline.history().iter().last()) {
+        return false;
+    }
+
+    if history_control().iter().any(|entry| entry == "erasedups") {
+        let entries = history_entries(readline);
+        if entries.iter().any(|(entry, _)| entry == line) {
+            let removed_before_mark = entries.iter().take(*last_entry).filter(|(entry, _)| entry == line).count();
+            *last_entry -= removed_before_mark;
+            replace_history(readline, entries.into_iter().filter(|(entry, _)| entry != line).collect());
+        }
+    }
+
+    let length = readline.history().len();
+    match readline.add_history_entry(line) {
+        // a full list drops its oldest entry, which shifts everything not yet saved
+        Ok(true) => {
+            if readline.history().len() == length {
+                *last_entry = last_entry.saturating_sub(1);
+            }
+            LINE_ADDED.store(true, Ordering::Relaxed);
+            true
+        }
+        // rustyline turns away a line starting with a space under ignorespace, and a repeat
+        Ok(false) => {
+            let ignores_space = history_control().iter().any(|entry| entry == "ignorespace" || entry == "ignoreboth");
+            !(ignores_space && line.starts_with(char::is_whitespace))
+        }
+        Err(e) => {
+            println!("Error while keeping history: {}", e);
+            false
+        }
+    }
+}
+
+// an empty HISTFILE turns saving off, the command store included
+pub fn is_saving_history() -> bool {
+    history_file(None).is_some()
+}
+
+// the list as it was before the line being run was added
+pub fn entries_before_line(readline: &Editor<MyHelper, ShellHistory>) -> Vec<String> {
+    let mut entries: Vec<String> = readline.history().iter().cloned().collect();
+    if LINE_ADDED.load(Ordering::Relaxed) {
+        entries.pop();
+    }
+    entries
+}
+
+// takes the line being run back out, for builtins that stand in for their own line
+pub fn forget_line(readline: &mut Editor<MyHelper, ShellHistory>, last_entry: &mut usize) {
+    if !LINE_ADDED.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let mut entries = history_entries(readline);
+    entries.pop();
+    replace_history(readline, entries);
+    *last_entry = (*last_entry).min(readline.history().len());
+}
+
+fn rewrite_history_file(path: &Path, entries: &[TimedEntry]) -> std::io::Result<()> {
+    let mut contents: Vec<u8> = Vec::new();
+    write_entries(&mut contents, entries, false)?;
+    replace_file(path, &contents)
+}
+
+// keeps only the newest HISTFILESIZE entries of the file, the caller holds the lock
+fn truncate_history_file(path: &Path) -> std::io::Result<()> {
+    let Some(limit) = history_file_size() else {
+        return Ok(());
+    };
+    let entries = read_history_entries(path)?;
+    if entries.len() <= limit {
+        return Ok(());
+    }
+    rewrite_history_file(path, &entries[entries.len() - limit..])?;
+    HISTFILE_LINES.store(limit, Ordering::Relaxed);
+    Ok(())
+}
+
+// an empty HISTFILE turns saving off, like in bash
+fn history_file(arg: Option<&str>) -> Option<PathBuf> {
+    match arg {
+        Some(file) => Some(PathBuf::from(file)),
+        None => env::var_os("HISTFILE").filter(|file| !file.is_empty()).map(PathBuf::from),
+    }
+}
+
+// an unset HISTFILE defaults to $XDG_STATE_HOME/shelly/history, falling back to ~/.local/state
+pub fn init_history_file() -> Option<PathBuf> {
+    if env::var_os("HISTFILE").is_none() {
+        let location = state_file("history")?;
+        // made now rather than on the first save, so `history -a` and histshare work from the start
+        if let Some(parent) = location.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        set_shell_var("HISTFILE", &location.to_string_lossy());
+    }
+    history_file(None)
+}
+
+fn history_entries(readline: &Editor<MyHelper, ShellHistory>) -> Vec<TimedEntry> {
+    readline.history().timed_entries()
+}
+
+fn replace_history(readline: &mut Editor<MyHelper, ShellHistory>, entries: Vec<TimedEntry>) {
+    readline.history_mut().replace(entries);
+}
+
+// entries read from a file go in front of the ones this session has not saved yet,
+// so everything from `last_entry` on is still exactly what `-a` and exit have to write
+fn merge_entries(readline: &mut Editor<MyHelper, ShellHistory>, loaded: Vec<TimedEntry>, last_entry: &mut usize) {
+    if loaded.is_empty() {
+        return;
+    }
+    let mut entries = history_entries(readline);
+    let unsaved = entries.split_off((*last_entry).min(entries.len()));
+    let unsaved_count = unsaved.len();
+    entries.extend(loaded);
+    entries.extend(unsaved);
+    replace_history(readline, entries);
+    *last_entry = readline.history().len().saturating_sub(unsaved_count);
+}
+
+fn read_file_into_history(
+    readline: &mut Editor<MyHelper, ShellHistory>,
+    path: &Path,
+    last_entry: &mut usize,
+) -> std::io::Result<()> {
+    let entries = read_history_entries(path)?;
+    HISTFILE_LINES.store(entries.len(), Ordering::Relaxed);
+    merge_entries(readline, entries, last_entry);
+    Ok(())
+}
+
+// only the entries other sessions added since we last looked
+fn read_new_entries(
+    readline: &mut Editor<MyHelper, ShellHistory>,
+    path: &Path,
+    last_entry: &mut usize,
+) -> std::io::Result<()> {
+    let mut entries = read_history_entries(path)?;
+    let total = entries.len();
+    // a file that shrank was truncated or rewritten elsewhere, there is no telling what is new
+    let seen = HISTFILE_LINES.load(Ordering::Relaxed);
+    let new_entries = if seen <= total { entries.split_off(seen) } else { Vec::new() };
+    HISTFILE_LINES.store(total, Ordering::Relaxed);
+    merge_entries(readline, new_entries, last_entry);
+    Ok(())
+}
+
+fn append_new_entries(
+    readline: &mut Editor<MyHelper, ShellHistory>,
+    path: &Path,
+    last_entry: &mut usize,
+) -> std::io::Result<()> {
+    let entries = history_entries(readline);
+    let unsaved = &entries[(*last_entry).min(entries.len())..];
+    if !unsaved.is_empty() {
+        let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+        write_entries(&mut file, unsaved, true)?;
+        HISTFILE_LINES.fetch_add(unsaved.len(), Ordering::Relaxed);
+        truncate_history_file(path)?;
+    }
+    *last_entry = entries.len();
+    Ok(())
+}
+
+// loads `path` into the list, used for $HISTFILE on startup
+pub fn load_history_file(
+    readline: &mut Editor<MyHelper, ShellHistory>,
+    path: &Path,
+    last_entry: &mut usize,
+) -> std::io::Result<()> {
+    let _lock = lock_file(path, false);
+    read_file_into_history(readline, path, last_entry)
+}
+
+// with HISTTIMEFORMAT set every entry is prefixed with its time, `??` when it has none
+fn list_entries(readline: &Editor<MyHelper, ShellHistory>, limit: Option<usize>) -> String {
+    let time_format = env::var("HISTTIMEFORMAT").ok().filter(|format| !format.is_empty());
+    let history = readline.history();
+    let length = history.len();
+    let start_index = limit.map_or(0, |limit| length.saturating_sub(limit));
+    let lines: Vec<String> = history
+        .iter()
+        .enumerate()
+        .skip(start_index)
+        .map(|(i, entry)| {
+            let time = match (&time_format, history.time(i)) {
+                (Some(format), Some(time)) => format_local_time(format, time),
+                (Some(_), None) => "??".to_string(),
+                (None, _) => String::new(),
+            };
+            format!("    {}  {}{}", i + 1, time, entry)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+// `-d` takes an offset, a negative one counting back from the end, or a `start-end` range
+fn delete_range(spec: &str, length: usize) -> Option<(usize, usize)> {
+    let position = |value: &str| -> Option<usize> {
+        let index: i64 = value.parse().ok()?;
+        let index = if index < 0 { length as i64 + index + 1 } else { index };
+        if index < 1 || index as usize > length {
+            None
+        } else {
+            Some(index as usize - 1)
+        }
+    };
+
+    // the first `-` of a range may also be the sign of a negative start
+    if let Some(split) = spec.char_indices().skip(1).find(|(_, c)| *c == '-').map(|(i, _)| i) {
+        let start = position(&spec[..split])?;
+        let end = position(&spec[split + 1..])?;
+        return if start <= end { Some((start, end)) } else { None };
+    }
+    let index = position(spec)?;
+    Some((index, index))
+}
+
+fn history_output(
+    readline: &mut Editor<MyHelper, ShellHistory>,
+    args: &[String],
+    command: &str,
+    last_entry: &mut usize,
+) -> Result<String, String> {
+    if args.iter().any(|arg| is_store_option(arg)) {
+        return store_output(args, command);
+    }
+
+    let command = command.trim();
+    let mut action: Option<char> = None;
+    let mut clear = false;
+    let mut operands: Vec<&String> = Vec::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i];
+        i += 1;
+        if arg == "--" {
+            operands.extend(&args[i..]);
+            break;
+        }
+        // everything after -p or -s is text, even if it looks like an option
+        if action.is_some_and(|action| matches!(action, 'p' | 's')) {
+            operands.extend(&args[i - 1..]);
+            break;
+        }
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() && !flags.starts_with(|c: char| c.is_ascii_digit()) => {
+                for flag in flags.chars() {
+                    match flag {
+                        'c' => clear = true,
+                        'd' | 'a' | 'n' | 'r' | 'w' | 'p' | 's' => {
+                            if action.is_some_and(|action| action != flag) {
+                                return Err(format!("{}: cannot use more than one of -anrw", command));
+                            }
+                            action = Some(flag);
+                        }
+                        _ => return Err(format!("{}: -{}: invalid option", command, flag)),
+                    }
+                }
+            }
+            _ => operands.push(arg),
+        }
+    }
+
+    if clear {
+        let _ = readline.clear_history();
+        *last_entry = 0;
+        if action.is_none() {
+            return Ok(String::new());
+        }
+    }
+
+    match action {
+        None => {
+            if operands.len() > 1 {
+                return Err(format!("{}: too many arguments", command));
+            }
+            match operands.first() {
+                None => Ok(list_entries(readline, None)),
+                Some(limit) => match limit.parse::<usize>() {
+                    Ok(limit) => Ok(list_entries(readline, Some(limit))),
+                    Err(_) => Err(format!("{}: {}: numeric argument required", command, limit)),
+                },
+            }
+        }
+        Some('d') => {
+            let Some(spec) = operands.first() else {
+                return Err(format!("{}: -d: option requires an argument", command));
+            };
+            let mut entries = history_entries(readline);
+            let Some((start, end)) = delete_range(spec, entries.len()) else {
+                return Err(format!("{}: {}: history position out of range", command, spec));
+            };
+            entries.drain(start..=end);
+            replace_history(readline, entries);
+            let removed_before_mark = (end + 1).min(*last_entry).saturating_sub(start);
+            *last_entry -= removed_before_mark;
+            Ok(String::new())
+        }
+        Some('p') => {
+            // expanded against the list before this `history -p` line was added
+            let entries = entries_before_line(readline);
+            let mut lines: Vec<String> = Vec::new();
+            for operand in operands {
+                lines.push(expand_history(operand, &entries)?.line);
+            }
+            Ok(lines.join("\n"))
+        }
+        Some('s') => {
+            // the `history -s` line itself is replaced by its arguments
+            forget_line(readline, last_entry);
+            if !operands.is_empty() {
+                let joined: Vec<&str> = operands.iter().map(|operand| operand.as_str()).collect();
+                let mut entries = history_entries(readline);
+                entries.push((joined.join(" "), Some(now_epoch())));
+                replace_history(readline, entries);
+            }
+            Ok(String::new())
+        }
+        Some(file_action) => {
+            if operands.len() > 1 {
+                return Err(format!("{}: too many arguments", command));
+            }
+            let Some(path) = history_file(operands.first().map(|operand| operand.as_str())) else {
+                return Err(format!("{}: HISTFILE not set", command));
+            };
+            history_file_action(readline, file_action, &path, last_entry)
+                .map(|_| String::new())
+                .map_err(|e| format!("{}: {}: {}", command, path.display(), describe_io_error(&e)))
+        }
+    }
+}
+
+fn history_file_action(
+    readline: &mut Editor<MyHelper, ShellHistory>,
+    action: char,
+    path: &Path,
+    last_entry: &mut usize,
+) -> std::io::Result<()> {
+    let _lock = lock_file(path, action != 'r' && action != 'n');
+    match action {
+        'r' => read_file_into_history(readline, path, last_entry),
+        'n' => read_new_entries(readline, path, last_entry),
+        'w' => {
+            rewrite_history_file(path, &history_entries(readline))?;
+            *last_entry = readline.history().len();
+            HISTFILE_LINES.store(readline.history().len(), Ordering::Relaxed);
+            truncate_history_file(path)
+        }
+        _ => append_new_entries(readline, path, last_entry),
+    }
+}
+
+// `shopt -s histshare`: before every prompt pick up what other sessions saved, then save
+// ours, like bash's `history -a; history -n` in PROMPT_COMMAND but in the order that
+// keeps the seen-entry count right
+pub fn share_history(readline: &mut Editor<MyHelper, ShellHistory>, last_entry: &mut usize) {
+    let Some(path) = history_file(None) else {
+        return;
+    };
+    let _lock = lock_file(&path, true);
+    if path.exists() {
+        let _ = read_new_entries(readline, &path, last_entry);
+    }
+    let _ = append_new_entries(readline, &path, last_entry);
+}
+
+pub fn history_handler(
+    readline: &mut Editor<MyHelper, ShellHistory>,
+    args: &[String],
+    command: &str,
+    redirect: bool,
+    redirects: Vec<(String, String)>,
+    last_entry: &mut usize,
+) {
+    let result = history_output(readline, args, command, last_entry);
+    builtin_output_handler(result, redirect, redirects);
+}
+
+// runs however the shell ends, the file and its directory are made on the first save
+pub fn append_history_on_exit(readline: &mut Editor<MyHelper, ShellHistory>, last_entry: &mut usize) {
+    if let Some(path) = history_file(None) {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _lock = lock_file(&path, true);
+        if append_new_entries(readline, &path, last_entry).is_err() {
+            println!("Sorry could not save ur cmd history")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(line: &str, time: Option<i64>) -> TimedEntry {
+        (line.to_string(), time)
+    }
+
+    fn written(entries: &[TimedEntry], appending: bool) -> String {
+        let mut contents: Vec<u8> = Vec::new();
+        write_entries(&mut contents, entries, appending).unwrap();
+        String::from_utf8(contents).unwrap()
+    }
+
+    #[test]
+    fn reads_plain_and_timed_files() {
+        assert_eq!(parse_history("ls\ncd /tmp\n"), [entry("ls", None), entry("cd /tmp", None)]);
+        assert_eq!(parse_history("#V2\nls\n"), [entry("ls", None)]);
+        assert_eq!(
+            parse_history("old\n#100\necho one\n#200\nfor x in a\ndo\ndone\n#abc\n"),
+            [entry("old", None), entry("echo one", Some(100)), entry("for x in a\ndo\ndone\n#abc", Some(200))]
+        );
+        // a comment that is not a timestamp is an entry of its own in a plain file
+        assert_eq!(parse_history("#abc\nls\n"), [entry("#abc", None), entry("ls", None)]);
+    }
+
+    #[test]
+    fn untimed_entries_after_timed_ones_get_a_time() {
+        let entries = [entry("plain", None), entry("a", Some(5)), entry("b", None)];
+        assert_eq!(written(&entries, false), "plain\n#5\na\n#5\nb\n");
+        assert!(written(&[entry("c", None)], true).starts_with('#'));
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries = [
+            entry("echo one", Some(100)),
+            entry("echo \"two\nlines\"", Some(200)),
+            entry("for x in a\ndo\n  echo $x # done\ndone", Some(300)),
+            entry("ls", Some(400)),
+        ];
+        let contents = written(&entries, false);
+        assert!(contents.starts_with("#100\necho one\n#200\necho \"two\nlines\"\n#300\n"));
+        assert_eq!(parse_history(&contents), entries);
+    }
+
+    #[test]
+    fn ignore_patterns() {
+        let previous = "make".to_string();
+        let cases = [
+            ("ls:cd *", "ls", true),
+            ("ls:cd *", "ls -l", false),
+            ("ls:cd *", "cd /tmp", true),
+            ("[ ]*", " secret", true),
+            ("&", "make", true),
+            ("&", "make test", false),
+            ("a\\:b", "a:b", true),
+            ("a\\:b", "a", false),
+            ("::", "", false),
+        ];
+        for (patterns, line, expected) in cases {
+            assert_eq!(matches_ignore_patterns(patterns, line, Some(&previous)), expected, "{:?} {:?}", patterns, line);
+        }
+        assert!(!matches_ignore_patterns("&", "make", None));
+    }
+}
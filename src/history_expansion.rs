@@ -0,0 +1,514 @@
+use std::sync::Mutex;
+
+// the last `:s/old/new/`, reused by `:&` and by an empty `old`
+static LAST_SUBSTITUTION: Mutex<Option<(String, String)>> = Mutex::new(None);
+
+pub struct Expansion {
+    pub line: String,
+    // `:p` asks for the result to be shown and stored but not run
+    pub print_only: bool,
+    pub expanded: bool,
+}
+
+pub fn needs_expansion(line: &str) -> bool {
+    line.starts_with('^') || line.contains('!')
+}
+
+// splits an event into words the way bash counts them: quotes stay attached and
+// each run of `|&;<>` is a word of its own
+fn split_words(line: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_single_quotes = false;
+    let mut in_double_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(character) = chars.next() {
+        match character {
+            '\\' if !in_single_quotes => {
+                current.push(character);
+                if let Some(next_char) = chars.next() {
+                    current.push(next_char);
+                }
+            }
+            '\'' if !in_double_quotes => {
+                in_single_quotes = !in_single_quotes;
+                current.push(character);
+            }
+            '"' if !in_single_quotes => {
+                in_double_quotes = !in_double_quotes;
+                current.push(character);
+            }
+            c if c.is_whitespace() && !in_single_quotes && !in_double_quotes => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            }
+            '|' | '&' | ';' | '<' | '>' if !in_single_quotes && !in_double_quotes => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+                current.push(character);
+                while let Some(&next_char) = chars.peek() {
+                    if !matches!(next_char, '|' | '&' | ';' | '<' | '>') {
+                        break;
+                    }
+                    current.push(next_char);
+                    chars.next();
+                }
+                words.push(std::mem::take(&mut current));
+            }
+            _ => current.push(character),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+fn parse_number(chars: &[char], at: &mut usize) -> Option<usize> {
+    let start = *at;
+    while *at < chars.len() && chars[*at].is_ascii_digit() {
+        *at += 1;
+    }
+    if *at == start {
+        return None;
+    }
+    chars[start..*at].iter().collect::<String>().parse().ok()
+}
+
+struct Event {
+    text: String,
+    // the word a `!?string?` search hit, for the `%` designator
+    matched_word: Option<usize>,
+}
+
+fn parse_event(
+    chars: &[char],
+    at: &mut usize,
+    history: &[String],
+    line_so_far: &str,
+) -> Result<Event, String> {
+    let start = *at - 1;
+    let previous = || history.last().cloned();
+    let not_found = |at: usize| {
+        let spec: String = chars[start..at].iter().collect();
+        format!("{}: event not found", spec)
+    };
+
+    let event = match chars.get(*at) {
+        Some('!') => {
+            *at += 1;
+            previous()
+        }
+        Some('#') => {
+            *at += 1;
+            Some(line_so_far.to_string())
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let n = parse_number(chars, at).unwrap_or(0);
+            n.checked_sub(1).and_then(|index| history.get(index).cloned())
+        }
+        Some('-') if chars.get(*at + 1).is_some_and(|c| c.is_ascii_digit()) => {
+            *at += 1;
+            let n = parse_number(chars, at).unwrap_or(0);
+            history.len().checked_sub(n).and_then(|index| history.get(index).cloned())
+        }
+        Some('?') => {
+            *at += 1;
+            let needle_start = *at;
+            while *at < chars.len() && chars[*at] != '?' {
+                *at += 1;
+            }
+            let needle: String = chars[needle_start..*at].iter().collect();
+            if *at < chars.len() {
+                *at += 1;
+            }
+            let found = history.iter().rev().find(|entry| entry.contains(&needle));
+            return match found {
+                Some(entry) => {
+                    let matched_word = split_words(entry).iter().position(|word| word.contains(&needle));
+                    Ok(Event {
+                        text: entry.clone(),
+                        matched_word,
+                    })
+                }
+                None => Err(not_found(*at)),
+            };
+        }
+        // no event at all, `!$` and friends mean the previous command
+        Some(':' | '^' | '$' | '*' | '%') => previous(),
+        _ => {
+            let prefix_start = *at;
+            while *at < chars.len()
+                && !chars[*at].is_whitespace()
+                && !matches!(chars[*at], ':' | '"' | '\'' | ';' | '&' | '|' | '(' | ')' | '<' | '>')
+            {
+                *at += 1;
+            }
+            let prefix: String = chars[prefix_start..*at].iter().collect();
+            if prefix.is_empty() {
+                None
+            } else {
+                history.iter().rev().find(|entry| entry.starts_with(&prefix)).cloned()
+            }
+        }
+    };
+
+    match event {
+        Some(text) => Ok(Event {
+            text,
+            matched_word: None,
+        }),
+        None => Err(not_found(*at)),
+    }
+}
+
+// one end of a word range: a number, `^` (1), `$` (last) or `%` (the `?` match)
+fn parse_word_index(chars: &[char], at: &mut usize, last: usize, matched: Option<usize>) -> Option<usize> {
+    match chars.get(*at) {
+        Some('^') => {
+            *at += 1;
+            Some(1)
+        }
+        Some('$') => {
+            *at += 1;
+            Some(last)
+        }
+        Some('%') => {
+            *at += 1;
+            matched
+        }
+        Some(c) if c.is_ascii_digit() => parse_number(chars, at),
+        _ => None,
+    }
+}
+
+fn select_words(chars: &[char], at: &mut usize, event: &Event, spec_start: usize) -> Result<String, String> {
+    let words = split_words(&event.text);
+    let last = words.len().saturating_sub(1);
+    let bad_specifier = |at: usize| {
+        let spec: String = chars[spec_start..at].iter().collect();
+        format!("{}: bad word specifier", spec)
+    };
+
+    let has_colon = chars.get(*at) == Some(&':')
+        && chars
+            .get(*at + 1)
+            .is_some_and(|c| c.is_ascii_digit() || matches!(c, '^' | '$' | '*' | '-' | '%'));
+    let bare = matches!(chars.get(*at), Some('^' | '$' | '*' | '%'));
+    if !has_colon && !bare {
+        return Ok(event.text.clone());
+    }
+    if has_colon {
+        *at += 1;
+    }
+
+    let (first, end) = if chars.get(*at) == Some(&'*') {
+        *at += 1;
+        if words.len() < 2 {
+            return Ok(String::new());
+        }
+        (1, last)
+    } else {
+        let first = if chars.get(*at) == Some(&'-') {
+            0
+        } else {
+            parse_word_index(chars, at, last, event.matched_word).ok_or_else(|| bad_specifier(*at))?
+        };
+        match chars.get(*at) {
+            Some('*') => {
+                *at += 1;
+                (first, last)
+            }
+            Some('-') => {
+                *at += 1;
+                match parse_word_index(chars, at, last, event.matched_word) {
+                    Some(end) => (first, end),
+                    // `x-` stops short of the last word
+                    None => (first, last.saturating_sub(1)),
+                }
+            }
+            _ => (first, first),
+        }
+    };
+
+    if first > end || end > last || words.is_empty() {
+        return Err(bad_specifier(*at));
+    }
+    Ok(words[first..=end].join(" "))
+}
+
+fn read_until(chars: &[char], at: &mut usize, delimiter: char) -> String {
+    let mut text = String::new();
+    while *at < chars.len() && chars[*at] != delimiter {
+        if chars[*at] == '\\' && chars.get(*at + 1) == Some(&delimiter) {
+            *at += 1;
+        }
+        text.push(chars[*at]);
+        *at += 1;
+    }
+    if *at < chars.len() {
+        *at += 1;
+    }
+    text
+}
+
+fn substitute(text: &str, old: &str, new: &str, global: bool) -> Result<String, String> {
+    if old.is_empty() || !text.contains(old) {
+        return Err(format!(":s/{}/{}/: substitution failed", old, new));
+    }
+    // `&` in the replacement stands for the text being replaced
+    let new = new.replace('&', old);
+    Ok(if global { text.replace(old, &new) } else { text.replacen(old, &new, 1) })
+}
+
+fn apply_modifiers(chars: &[char], at: &mut usize, mut text: String, print_only: &mut bool) -> Result<String, String> {
+    while chars.get(*at) == Some(&':') && chars.get(*at + 1).is_some_and(|c| c.is_ascii_alphabetic() || *c == '&') {
+        *at += 1;
+        let mut global = false;
+        if matches!(chars.get(*at), Some('g' | 'a')) && matches!(chars.get(*at + 1), Some('s' | '&')) {
+            global = true;
+            *at += 1;
+        }
+        let modifier = chars[*at];
+        *at += 1;
+        match modifier {
+            'h' => {
+                match text.rfind('/') {
+                    Some(0) => text.truncate(1),
+                    Some(index) => text.truncate(index),
+                    None => {}
+                }
+            }
+            't' => {
+                if let Some(index) = text.rfind('/') {
+                    text = text[index + 1..].to_string();
+                }
+            }
+            'r' => {
+                let name_start = text.rfind('/').map_or(0, |index| index + 1);
+                if let Some(index) = text[name_start..].rfind('.') {
+                    text.truncate(name_start + index);
+                }
+            }
+            'e' => {
+                let name_start = text.rfind('/').map_or(0, |index| index + 1);
+                text = match text[name_start..].rfind('.') {
+                    Some(index) => text[name_start + index..].to_string(),
+                    None => String::new(),
+                };
+            }
+            'p' => *print_only = true,
+            'q' => text = format!("'{}'", text.replace('\'', "'\\''")),
+            'x' => {
+                let quoted: Vec<String> = text
+                    .split_whitespace()
+                    .map(|word| format!("'{}'", word.replace('\'', "'\\''")))
+                    .collect();
+                text = quoted.join(" ");
+            }
+            's' => {
+                let Some(&delimiter) = chars.get(*at) else {
+                    return Err(":s: missing delimiter".to_string());
+                };
+                *at += 1;
+                let mut old = read_until(chars, at, delimiter);
+                let new = read_until(chars, at, delimiter);
+                let mut last_substitution = LAST_SUBSTITUTION.lock().map_err(|_| "history: substitution unavailable".to_string())?;
+                if old.is_empty() {
+                    old = last_substitution.as_ref().map(|(old, _)| old.clone()).unwrap_or_default();
+                }
+                text = substitute(&text, &old, &new, global)?;
+                *last_substitution = Some((old, new));
+            }
+            '&' => {
+                let last_substitution = LAST_SUBSTITUTION.lock().map_err(|_| "history: substitution unavailable".to_string())?;
+                let Some((old, new)) = last_substitution.as_ref() else {
+                    return Err(":&: no previous substitution".to_string());
+                };
+                text = substitute(&text, old, new, global)?;
+            }
+            other => return Err(format!(":{}: unrecognized history modifier", other)),
+        }
+    }
+    Ok(text)
+}
+
+// expands `!` references against `history` (oldest first), the line without them comes back untouched
+pub fn expand_history(line: &str, history: &[String]) -> Result<Expansion, String> {
+    let mut expansion = Expansion {
+        line: String::new(),
+        print_only: false,
+        expanded: false,
+    };
+    if !needs_expansion(line) {
+        expansion.line = line.to_string();
+        return Ok(expansion);
+    }
+
+    // `^old^new^` is shorthand for `!!:s^old^new^`
+    let rewritten;
+    let line = if let Some(rest) = line.strip_prefix('^') {
+        let chars: Vec<char> = rest.chars().collect();
+        let mut at = 0;
+        let old = read_until(&chars, &mut at, '^');
+        let new = read_until(&chars, &mut at, '^');
+        let tail: String = chars[at..].iter().collect();
+        rewritten = format!("!!:s^{}^{}^{}", old, new, tail);
+        rewritten.as_str()
+    } else {
+        line
+    };
+
+    let chars: Vec<char> = line.chars().collect();
+    let mut in_single_quotes = false;
+    let mut in_double_quotes = false;
+    let mut at = 0;
+
+    while at < chars.len() {
+        let character = chars[at];
+        at += 1;
+        match character {
+            '\\' if !in_single_quotes => {
+                expansion.line.push(character);
+                if at < chars.len() {
+                    expansion.line.push(chars[at]);
+                    at += 1;
+                }
+            }
+            '\'' if !in_double_quotes => {
+                in_single_quotes = !in_single_quotes;
+                expansion.line.push(character);
+            }
+            '"' if !in_single_quotes => {
+                in_double_quotes = !in_double_quotes;
+                expansion.line.push(character);
+            }
+            '!' if !in_single_quotes => {
+                let literal = match chars.get(at) {
+                    None => true,
+                    Some(c) => c.is_whitespace() || matches!(c, '=' | '(') || (in_double_quotes && *c == '"'),
+                };
+                if literal {
+                    expansion.line.push(character);
+                    continue;
+                }
+
+                let spec_start = at - 1;
+                let event = parse_event(&chars, &mut at, history, &expansion.line)?;
+                let words = select_words(&chars, &mut at, &event, spec_start)?;
+                let text = apply_modifiers(&chars, &mut at, words, &mut expansion.print_only)?;
+                expansion.line.push_str(&text);
+                expansion.expanded = true;
+            }
+            _ => expansion.line.push(character),
+        }
+    }
+
+    Ok(expansion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> Vec<String> {
+        ["cd /tmp", "ls -l src/main.rs | wc -l", "git commit -m 'a b'", "cat notes.tar.gz"]
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect()
+    }
+
+    fn expand(line: &str) -> Result<String, String> {
+        expand_history(line, &history()).map(|expansion| expansion.line)
+    }
+
+    #[test]
+    fn events() {
+        let cases = [
+            ("!!", "cat notes.tar.gz"),
+            ("sudo !!", "sudo cat notes.tar.gz"),
+            ("!1", "cd /tmp"),
+            ("!-2", "git commit -m 'a b'"),
+            ("!ls", "ls -l src/main.rs | wc -l"),
+            ("!?commit?", "git commit -m 'a b'"),
+            ("echo a !#", "echo a echo a "),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(expand(line).as_deref(), Ok(expected), "{:?}", line);
+        }
+        assert_eq!(expand("!9").unwrap_err(), "!9: event not found");
+        assert_eq!(expand("!nope").unwrap_err(), "!nope: event not found");
+    }
+
+    #[test]
+    fn word_designators() {
+        let cases = [
+            ("echo !$", "echo notes.tar.gz"),
+            ("echo !^", "echo notes.tar.gz"),
+            ("echo !2:0", "echo ls"),
+            ("echo !2:1-2", "echo -l src/main.rs"),
+            ("echo !2:3", "echo |"),
+            ("echo !2:2*", "echo src/main.rs | wc -l"),
+            ("echo !2:2-", "echo src/main.rs | wc"),
+            ("echo !3:*", "echo commit -m 'a b'"),
+            ("echo !?src?:%", "echo src/main.rs"),
+            ("echo !1*", "echo /tmp"),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(expand(line).as_deref(), Ok(expected), "{:?}", line);
+        }
+        assert_eq!(expand("echo !1:5").unwrap_err(), "!1:5: bad word specifier");
+    }
+
+    #[test]
+    fn modifiers() {
+        let cases = [
+            ("echo !2:2:h", "echo src"),
+            ("echo !2:2:t", "echo main.rs"),
+            ("echo !$:r", "echo notes.tar"),
+            ("echo !$:e", "echo .gz"),
+            ("echo !1:1:h", "echo /"),
+            ("!3:2:q", "'-m'"),
+            ("!1:x", "'cd' '/tmp'"),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(expand(line).as_deref(), Ok(expected), "{:?}", line);
+        }
+        let expansion = expand_history("!1:p", &history()).unwrap();
+        assert!(expansion.print_only);
+        assert_eq!(expand("!!:z").unwrap_err(), ":z: unrecognized history modifier");
+    }
+
+    // the substitutions share the remembered one, so they run in order in one test
+    #[test]
+    fn substitutions() {
+        let cases = [
+            ("^tar^zip^", "cat notes.zip.gz"),
+            ("^a^o^ -n", "cot notes.tar.gz -n"),
+            ("!2:s/l/L/", "Ls -l src/main.rs | wc -l"),
+            ("!2:gs/l/L/", "Ls -L src/main.rs | wc -L"),
+            ("!2:&", "Ls -l src/main.rs | wc -l"),
+            ("!2:g&", "Ls -L src/main.rs | wc -L"),
+            ("!2:s//[&]/", "[l]s -l src/main.rs | wc -l"),
+            ("!1:s|/tmp|a\\|b|", "cd a|b"),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(expand(line).as_deref(), Ok(expected), "{:?}", line);
+        }
+        assert_eq!(expand("^xyz^abc^").unwrap_err(), ":s/xyz/abc/: substitution failed");
+    }
+
+    #[test]
+    fn quoting_and_literal_bangs() {
+        let cases = ["echo '!!'", "echo \\!!", "echo hi!", "echo ! x", "test a != b", "echo \"wow!\""];
+        for line in cases {
+            let expansion = expand_history(line, &history()).unwrap();
+            assert_eq!(expansion.line, line);
+            assert!(!expansion.expanded, "{:?}", line);
+        }
+        assert_eq!(expand("echo \"!!\"").as_deref(), Ok("echo \"cat notes.tar.gz\""));
+    }
+}
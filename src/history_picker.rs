@@ -0,0 +1,313 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, Movement, RepeatCount};
+
+use crate::history::TimedEntry;
+use crate::history_store::{command_usage, current_directory, CommandUsage};
+use crate::time_format::now_epoch;
+
+// the history as of the current prompt, an event handler has no way to reach the editor's
+static HISTORY: Mutex<Vec<TimedEntry>> = Mutex::new(Vec::new());
+
+pub fn set_picker_history(entries: Vec<TimedEntry>) {
+    if let Ok(mut history) = HISTORY.lock() {
+        *history = entries;
+    }
+}
+
+// every distinct line of the history, newest first. The store only says how often and how
+// lately each one was run, and in which directory
+fn history_candidates(directory: Option<&str>) -> Vec<CommandUsage> {
+    let mut usage: HashMap<String, CommandUsage> = command_usage(directory)
+        .into_iter()
+        .map(|usage| (usage.command.clone(), usage))
+        .collect();
+    let history = HISTORY.lock().map(|history| history.clone()).unwrap_or_default();
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut candidates: Vec<CommandUsage> = Vec::new();
+    for (line, time) in history.iter().rev() {
+        if !seen.insert(line) {
+            continue;
+        }
+        match usage.remove(line) {
+            Some(usage) => candidates.push(usage),
+            // lines never recorded in the store are not known to belong to any directory
+            None if directory.is_none() => candidates.push(CommandUsage {
+                command: line.clone(),
+                count: 1,
+                last_used: time.unwrap_or(0),
+            }),
+            None => {}
+        }
+    }
+    candidates
+}
+
+// full-screen fuzzy finder over the history, bound to Ctrl-R in main
+pub struct HistoryPicker;
+
+enum Key {
+    Char(char),
+    Up,
+    Down,
+    Enter,
+    Cancel,
+    Backspace,
+    ClearQuery,
+    ToggleDirectory,
+    Other,
+}
+
+struct Match<'a> {
+    usage: &'a CommandUsage,
+    score: f64,
+    positions: Vec<usize>,
+}
+
+impl ConditionalEventHandler for HistoryPicker {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        // SAFETY: isatty only inspects the descriptors
+        let interactive = unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDOUT_FILENO) == 1 };
+        if !interactive {
+            return None;
+        }
+        match pick(ctx.line()) {
+            // Insert leaves the cursor after the text, Replace at the start of it
+            Some(selection) if ctx.line().is_empty() => Some(Cmd::Insert(1, selection)),
+            Some(selection) => Some(Cmd::Replace(Movement::WholeBuffer, Some(selection))),
+            None => Some(Cmd::Repaint),
+        }
+    }
+}
+
+// subsequence match, ignoring case unless the query has capitals. Consecutive characters and
+// ones at the start of a word score higher, skipped characters cost a little
+fn fuzzy_match(query: &str, candidate: &str) -> Option<(f64, Vec<usize>)> {
+    if query.is_empty() {
+        return Some((0.0, Vec::new()));
+    }
+    let ignore_case = !query.chars().any(char::is_uppercase);
+    let fold = |c: char| if ignore_case { c.to_lowercase().next().unwrap_or(c) } else { c };
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+
+    let mut positions = Vec::with_capacity(query.len());
+    let mut score = 0.0;
+    let mut next = 0;
+    for wanted in query {
+        let found = (next..candidate.len()).find(|&i| fold(candidate[i]) == wanted)?;
+        score += 16.0;
+        if positions.last().is_some_and(|last| last + 1 == found) {
+            score += 8.0;
+        }
+        if found == 0 || matches!(candidate[found - 1], ' ' | '/' | '-' | '_' | '.') {
+            score += 8.0;
+        }
+        score -= (found - next) as f64 * 0.5;
+        positions.push(found);
+        next = found + 1;
+    }
+    Some((score, positions))
+}
+
+// use count weighted by age, the same buckets `z` uses for directories
+fn frecency(usage: &CommandUsage, now: i64) -> f64 {
+    let age = now.saturating_sub(usage.last_used);
+    let count = usage.count as f64;
+    if age < 3600 {
+        count * 4.0
+    } else if age < 86400 {
+        count * 2.0
+    } else if age < 604800 {
+        count / 2.0
+    } else {
+        count / 4.0
+    }
+}
+
+fn ranked<'a>(candidates: &'a [CommandUsage], query: &str) -> Vec<Match<'a>> {
+    let now = now_epoch();
+    let mut matches: Vec<Match> = candidates
+        .iter()
+        .filter_map(|usage| {
+            let (fuzzy, positions) = fuzzy_match(query, &usage.command)?;
+            // frecency grows slowly so a much better match still wins
+            let score = fuzzy + (1.0 + frecency(usage, now)).ln() * 4.0;
+            Some(Match { usage, score, positions })
+        })
+        .collect();
+    matches.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| b.usage.last_used.cmp(&a.usage.last_used))
+    });
+    matches
+}
+
+pub fn terminal_size() -> (usize, usize) {
+    // SAFETY: winsize is plain data that TIOCGWINSZ fills in
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: the pointer is valid for the duration of the call
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result != 0 || size.ws_col == 0 || size.ws_row == 0 {
+        (80, 24)
+    } else {
+        (size.ws_col as usize, size.ws_row as usize)
+    }
+}
+
+fn read_byte(timeout_ms: i32) -> Option<u8> {
+    let mut poll = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: one valid pollfd is passed
+    if timeout_ms >= 0 && unsafe { libc::poll(&mut poll, 1, timeout_ms) } <= 0 {
+        return None;
+    }
+    let mut byte = 0u8;
+    // SAFETY: reads at most one byte into `byte`
+    let read = unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+    if read == 1 { Some(byte) } else { None }
+}
+
+// the terminal is still in rustyline's raw mode, so keys arrive byte by byte
+fn read_key() -> Key {
+    let Some(first) = read_byte(-1) else {
+        return Key::Cancel;
+    };
+    match first {
+        b'\r' | b'\n' => Key::Enter,
+        0x03 | 0x07 => Key::Cancel,
+        0x7f | 0x08 => Key::Backspace,
+        0x10 => Key::Up,
+        0x0e => Key::Down,
+        0x12 => Key::ToggleDirectory,
+        0x15 => Key::ClearQuery,
+        0x1b => match read_byte(30) {
+            // a lone escape
+            None => Key::Cancel,
+            Some(b'[' | b'O') => {
+                let mut last = 0;
+                while let Some(byte) = read_byte(30) {
+                    last = byte;
+                    if (0x40..=0x7e).contains(&byte) {
+                        break;
+                    }
+                }
+                match last {
+                    b'A' => Key::Up,
+                    b'B' => Key::Down,
+                    _ => Key::Other,
+                }
+            }
+            Some(_) => Key::Other,
+        },
+        byte if byte < 0x20 => Key::Other,
+        byte => {
+            let length = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..length {
+                match read_byte(30) {
+                    Some(next) => bytes.push(next),
+                    None => return Key::Other,
+                }
+            }
+            match std::str::from_utf8(&bytes).ok().and_then(|text| text.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Other,
+            }
+        }
+    }
+}
+
+// one entry cut to the screen width, matched characters in bold yellow
+fn render_entry(entry: &Match, selected: bool, width: usize) -> String {
+    let mut line = String::from(if selected { "\x1b[7m> " } else { "  " });
+    let mut positions = entry.positions.iter().peekable();
+    for (i, c) in entry.usage.command.chars().take(width.saturating_sub(2)).enumerate() {
+        let c = if c.is_control() { ' ' } else { c };
+        if positions.next_if(|position| **position == i).is_some() {
+            line.push_str("\x1b[1;33m");
+            line.push(c);
+            line.push_str("\x1b[22;39m");
+        } else {
+            line.push(c);
+        }
+    }
+    line.push_str("\x1b[0m");
+    line
+}
+
+fn render(out: &mut impl Write, query: &str, matches: &[Match], total: usize, selected: usize, directory: Option<&str>) -> io::Result<()> {
+    let (width, height) = terminal_size();
+    let rows = height.saturating_sub(2).max(1);
+    let first = selected.saturating_sub(rows - 1);
+
+    write!(out, "\x1b[H\x1b[2J")?;
+    let scope = directory.map_or("all directories".to_string(), |dir| dir.to_string());
+    let status = format!("  {}/{}  {}  (ctrl-r: toggle directory)", matches.len(), total, scope);
+    write!(out, "\x1b[2;1H\x1b[2m{}\x1b[0m", status.chars().take(width).collect::<String>())?;
+    for (row, entry) in matches.iter().enumerate().skip(first).take(rows) {
+        write!(out, "\x1b[{};1H{}", row - first + 3, render_entry(entry, row == selected, width))?;
+    }
+    let prompt: String = format!("> {}", query).chars().take(width).collect();
+    write!(out, "\x1b[1;1H{}", prompt)?;
+    out.flush()
+}
+
+// shows the picker on the alternate screen, returns the chosen command line
+fn pick(initial_query: &str) -> Option<String> {
+    let mut out = io::stdout();
+    let mut query = initial_query.to_string();
+    let mut selected = 0;
+    let mut directory: Option<String> = None;
+    let mut candidates = history_candidates(None);
+
+    let _ = write!(out, "\x1b[?1049h");
+    let selection = loop {
+        let matches = ranked(&candidates, &query);
+        selected = selected.min(matches.len().saturating_sub(1));
+        if render(&mut out, &query, &matches, candidates.len(), selected, directory.as_deref()).is_err() {
+            break None;
+        }
+        match read_key() {
+            Key::Enter => break matches.get(selected).map(|entry| entry.usage.command.clone()),
+            Key::Cancel => break None,
+            Key::Up => selected = selected.saturating_sub(1),
+            Key::Down => selected += 1,
+            Key::Backspace => {
+                query.pop();
+                selected = 0;
+            }
+            Key::ClearQuery => {
+                query.clear();
+                selected = 0;
+            }
+            Key::Char(c) => {
+                query.push(c);
+                selected = 0;
+            }
+            Key::ToggleDirectory => {
+                directory = match directory {
+                    Some(_) => None,
+                    None => Some(current_directory()),
+                };
+                candidates = history_candidates(directory.as_deref());
+                selected = 0;
+            }
+            Key::Other => {}
+        }
+    };
+    let _ = write!(out, "\x1b[?1049l");
+    let _ = out.flush();
+    selection
+}
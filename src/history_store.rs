@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::handler::{logical_pwd, normalize_logical};
+use crate::time_format::{format_local_time, now_epoch, parse_time};
+use crate::xdg::{data_file, lock_file, replace_file};
+
+// the newest records the store keeps unless SHELLY_STORE_SIZE says otherwise
+const DEFAULT_STORE_SIZE: usize = 10000;
+
+// how many records the store holds as far as this session knows, counted on the first write
+static STORE_RECORDS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+// one line of $XDG_DATA_HOME/shelly/history.jsonl
+struct CommandRecord {
+    command: String,
+    cwd: String,
+    exit: i64,
+    start: i64,
+    duration_ms: i64,
+    session: String,
+    host: String,
+}
+
+enum Value {
+    Text(String),
+    Number(i64),
+}
+
+// the pid plus the start time, so ids stay apart once pids get reused
+fn session_id() -> &'static str {
+    static SESSION: OnceLock<String> = OnceLock::new();
+    SESSION.get_or_init(|| format!("{}-{}", now_epoch(), std::process::id()))
+}
+
+pub fn host_name() -> &'static str {
+    static HOST: OnceLock<String> = OnceLock::new();
+    HOST.get_or_init(|| {
+        let mut buffer = [0u8; 256];
+        // SAFETY: the buffer length is passed along, gethostname never writes past it
+        let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+        if result != 0 {
+            return String::new();
+        }
+        let end = buffer.iter().position(|byte| *byte == 0).unwrap_or(buffer.len());
+        String::from_utf8_lossy(&buffer[..end]).into_owned()
+    })
+}
+
+pub fn current_directory() -> String {
+    logical_pwd()
+        .or_else(|| env::current_dir().ok().map(|dir| dir.display().to_string()))
+        .unwrap_or_default()
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn to_json(record: &CommandRecord) -> String {
+    format!(
+        "{{\"command\":{},\"cwd\":{},\"exit\":{},\"start\":{},\"duration_ms\":{},\"session\":{},\"host\":{}}}",
+        json_string(&record.command),
+        json_string(&record.cwd),
+        record.exit,
+        record.start,
+        record.duration_ms,
+        json_string(&record.session),
+        json_string(&record.host),
+    )
+}
+
+// the four hex digits of a `\\u` escape
+fn parse_code_unit(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<u32> {
+    let hex: String = chars.by_ref().take(4).collect();
+    if hex.len() != 4 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    if chars.next()? != '"' {
+        return None;
+    }
+    let mut text = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(text),
+            '\\' => match chars.next()? {
+                'n' => text.push('\n'),
+                'r' => text.push('\r'),
+                't' => text.push('\t'),
+                'b' => text.push('\u{8}'),
+                'f' => text.push('\u{c}'),
+                'u' => {
+                    let mut code = parse_code_unit(chars)?;
+                    // characters past U+FFFF come as a high and a low surrogate
+                    if (0xd800..0xdc00).contains(&code) {
+                        let mut ahead = chars.clone();
+                        if ahead.next() == Some('\\')
+                            && ahead.next() == Some('u')
+                            && let Some(low) = parse_code_unit(&mut ahead).filter(|low| (0xdc00..0xe000).contains(low))
+                        {
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            *chars = ahead;
+                        }
+                    }
+                    text.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                other => text.push(other),
+            },
+            c => text.push(c),
+        }
+    }
+}
+
+// just enough JSON for the flat objects this file holds, anything else is skipped
+fn parse_object(line: &str) -> Option<Vec<(String, Value)>> {
+    let mut chars = line.trim().chars().peekable();
+    if chars.next()? != '{' {
+        return None;
+    }
+    let mut fields = Vec::new();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace() || *c == ',') {
+            chars.next();
+        }
+        if chars.peek() == Some(&'}') {
+            return Some(fields);
+        }
+        let key = parse_string(&mut chars)?;
+        while chars.peek().is_some_and(|c| c.is_whitespace() || *c == ':') {
+            chars.next();
+        }
+        let value = if chars.peek() == Some(&'"') {
+            Value::Text(parse_string(&mut chars)?)
+        } else {
+            let mut number = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '-') {
+                number.push(c);
+            }
+            Value::Number(number.parse().ok()?)
+        };
+        fields.push((key, value));
+    }
+}
+
+fn from_json(line: &str) -> Option<CommandRecord> {
+    let fields = parse_object(line)?;
+    let text = |name: &str| {
+        fields.iter().find_map(|(key, value)| match value {
+            Value::Text(text) if key == name => Some(text.clone()),
+            _ => None,
+        })
+    };
+    let number = |name: &str| {
+        fields.iter().find_map(|(key, value)| match value {
+            Value::Number(number) if key == name => Some(*number),
+            _ => None,
+        })
+    };
+    Some(CommandRecord {
+        command: text("command")?,
+        cwd: text("cwd").unwrap_or_default(),
+        exit: number("exit").unwrap_or(0),
+        start: number("start")?,
+        duration_ms: number("duration_ms").unwrap_or(0),
+        session: text("session").unwrap_or_default(),
+        host: text("host").unwrap_or_default(),
+    })
+}
+
+// called once a command line has finished, the file is only ever appended to
+pub fn record_command(command: &str, cwd: &str, exit: i32, start: i64, duration: Duration) {
+    let Some(store) = data_file("history.jsonl") else {
+        return;
+    };
+    let record = CommandRecord {
+        command: command.to_string(),
+        cwd: cwd.to_string(),
+        exit: exit as i64,
+        start,
+        duration_ms: duration.as_millis() as i64,
+        session: session_id().to_string(),
+        host: host_name().to_string(),
+    };
+    if let Some(parent) = store.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    // held so a trim never drops a record another shell is appending
+    let _lock = lock_file(&store, true);
+    // a single write per record, so lines from several shells never interleave
+    if let Ok(mut file) = OpenOptions::new().append(true).create(true).open(&store) {
+        let _ = file.write_all(format!("{}\n", to_json(&record)).as_bytes());
+    }
+
+    let mut records = match STORE_RECORDS.load(Ordering::Relaxed) {
+        usize::MAX => fs::read_to_string(&store).map_or(0, |contents| contents.lines().count()),
+        records => records + 1,
+    };
+    // trimmed once it has grown a tenth past the limit, so not every command rewrites it
+    let limit = store_size();
+    if records > limit + limit / 10 && trim_store(&store, limit).is_ok() {
+        records = limit;
+    }
+    STORE_RECORDS.store(records, Ordering::Relaxed);
+}
+
+fn store_size() -> usize {
+    env::var("SHELLY_STORE_SIZE")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_STORE_SIZE)
+}
+
+// keeps the newest `limit` records
+fn trim_store(store: &Path, limit: usize) -> std::io::Result<()> {
+    let contents = fs::read_to_string(store)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let kept = &lines[lines.len().saturating_sub(limit)..];
+    replace_file(store, kept.iter().map(|line| format!("{}\n", line)).collect::<String>().as_bytes())
+}
+
+fn load_records(store: &Path) -> Vec<CommandRecord> {
+    let Ok(contents) = fs::read_to_string(store) else {
+        return Vec::new();
+    };
+    contents.lines().filter_map(from_json).collect()
+}
+
+fn format_duration(milliseconds: i64) -> String {
+    if milliseconds < 1000 {
+        format!("{}ms", milliseconds)
+    } else if milliseconds < 60_000 {
+        format!("{:.2}s", milliseconds as f64 / 1000.0)
+    } else {
+        format!("{}m{:02}s", milliseconds / 60_000, milliseconds / 1000 % 60)
+    }
+}
+
+// `--cwd[=DIR]`, `--failed`, `--session`, `--since TIME` and `--until TIME` filter the
+// recorded commands, a trailing N keeps only the last N matches
+pub fn is_store_option(arg: &str) -> bool {
+    let name = arg.split_once('=').map_or(arg, |(name, _)| name);
+    matches!(name, "--cwd" | "--failed" | "--session" | "--since" | "--until")
+}
+
+pub fn store_output(args: &[String], command: &str) -> Result<String, String> {
+    let command = command.trim();
+    let now = now_epoch();
+    let mut directory: Option<String> = None;
+    let mut failed_only = false;
+    let mut session_only = false;
+    let mut since: Option<i64> = None;
+    let mut until: Option<i64> = None;
+    let mut limit: Option<usize> = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        match name {
+            "--cwd" => {
+                directory = Some(match inline_value {
+                    Some(dir) => {
+                        // recorded directories are logical ones, so `..` is resolved the same way
+                        let path = normalize_logical(&Path::new(&current_directory()).join(dir));
+                        path.display().to_string()
+                    }
+                    None => current_directory(),
+                })
+            }
+            "--failed" => failed_only = true,
+            "--session" => session_only = true,
+            "--since" | "--until" => {
+                let Some(value) = inline_value.or_else(|| args.next().cloned()) else {
+                    return Err(format!("{}: {}: option requires an argument", command, name));
+                };
+                let Some(time) = parse_time(&value, now) else {
+                    return Err(format!("{}: {}: invalid time", command, value));
+                };
+                if name == "--since" {
+                    since = Some(time);
+                } else {
+                    until = Some(time);
+                }
+            }
+            _ if limit.is_none() && !arg.starts_with('-') => match arg.parse::<usize>() {
+                Ok(count) => limit = Some(count),
+                Err(_) => return Err(format!("{}: {}: numeric argument required", command, arg)),
+            },
+            _ if arg.starts_with('-') => return Err(format!("{}: {}: invalid option", command, arg)),
+            _ => return Err(format!("{}: too many arguments", command)),
+        }
+    }
+
+    let Some(store) = data_file("history.jsonl") else {
+        return Ok(String::new());
+    };
+    let records: Vec<CommandRecord> = load_records(&store)
+        .into_iter()
+        .filter(|record| directory.as_ref().is_none_or(|dir| record.cwd == *dir))
+        .filter(|record| !failed_only || record.exit != 0)
+        .filter(|record| !session_only || record.session == session_id())
+        .filter(|record| since.is_none_or(|since| record.start >= since))
+        .filter(|record| until.is_none_or(|until| record.start <= until))
+        .collect();
+
+    let time_format = env::var("HISTTIMEFORMAT")
+        .ok()
+        .filter(|format| !format.trim().is_empty())
+        .unwrap_or_else(|| "%F %T".to_string());
+    let skip = limit.map_or(0, |limit| records.len().saturating_sub(limit));
+    let lines: Vec<String> = records
+        .iter()
+        .skip(skip)
+        .map(|record| {
+            format!(
+                "{}  {:>3}  {:>7}  {}  {}",
+                format_local_time(&time_format, record.start).trim_end(),
+                record.exit,
+                format_duration(record.duration_ms),
+                record.cwd,
+                record.command
+            )
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+// how often and how lately a command line was run, for the history picker
+pub struct CommandUsage {
+    pub command: String,
+    pub count: usize,
+    pub last_used: i64,
+}
+
+// every distinct command in the store, optionally only those run in `directory`
+pub fn command_usage(directory: Option<&str>) -> Vec<CommandUsage> {
+    let Some(store) = data_file("history.jsonl") else {
+        return Vec::new();
+    };
+    let mut usage: Vec<CommandUsage> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for record in load_records(&store) {
+        if directory.is_some_and(|dir| record.cwd != dir) {
+            continue;
+        }
+        match positions.get(&record.command) {
+            Some(&index) => {
+                usage[index].count += 1;
+                usage[index].last_used = usage[index].last_used.max(record.start);
+            }
+            None => {
+                positions.insert(record.command.clone(), usage.len());
+                usage.push(CommandUsage {
+                    command: record.command,
+                    count: 1,
+                    last_used: record.start,
+                });
+            }
+        }
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(command: &str) -> CommandRecord {
+        CommandRecord {
+            command: command.to_string(),
+            cwd: "/home/me/My \"Docs\"".to_string(),
+            exit: -1,
+            start: 1700000000,
+            duration_ms: 42,
+            session: "1700000000-42".to_string(),
+            host: "box".to_string(),
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        for command in ["ls", "echo \"a\\b\"", "for x\ndo\tdone\r", "printf '\u{1}\u{1f}'", "echo été 😀"] {
+            let line = to_json(&record(command));
+            assert!(!line.contains('\n'), "{:?}", line);
+            let read = from_json(&line).unwrap();
+            assert_eq!(read.command, command);
+            assert_eq!(to_json(&read), line);
+        }
+        assert_eq!(to_json(&record("\u{1}")).split(',').next(), Some("{\"command\":\"\\u0001\""));
+    }
+
+    #[test]
+    fn escapes() {
+        let read = |json: &str| from_json(&format!("{{\"command\":\"{}\",\"start\":1}}", json)).map(|record| record.command);
+        let cases = [
+            ("\\u00e9", "é"),
+            ("\\ud83d\\ude00", "😀"),
+            ("\\ud83d", "\u{fffd}"),
+            ("\\ud83dx", "\u{fffd}x"),
+            ("\\ude00\\ud83d", "\u{fffd}\u{fffd}"),
+            ("\\/\\b\\f", "/\u{8}\u{c}"),
+        ];
+        for (json, expected) in cases {
+            assert_eq!(read(json).as_deref(), Some(expected), "{:?}", json);
+        }
+        assert_eq!(read("\\u12"), None);
+        assert_eq!(read("\\u+123"), None);
+    }
+
+    #[test]
+    fn objects_other_writers_made() {
+        let line = r#" { "start" : 5, "extra": "x", "command": "ls", "exit": 2 } "#;
+        let read = from_json(line).unwrap();
+        assert_eq!((read.command.as_str(), read.start, read.exit, read.cwd.as_str()), ("ls", 5, 2, ""));
+        // a record needs its command and start time
+        assert!(from_json(r#"{"command":"ls"}"#).is_none());
+        assert!(from_json(r#"{"start":1}"#).is_none());
+        assert!(from_json("not json").is_none());
+        assert!(from_json(r#"{"command":"ls","start":1"#).is_none());
+    }
+}
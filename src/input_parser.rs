@@ -1,266 +1,538 @@
-pub fn input_parser(
-    input: &str,
-) -> (
-    bool,
-    Vec<Vec<String>>,
-    bool,
-    Vec<(String, String)>,
-    String,
-    String,
-) {
-    let mut in_double_quotes: bool = false;
-    let mut in_single_quotes: bool = false;
-    let mut is_complete: bool = true;
-    let mut is_escaped: bool = false;
-    let mut is_path: bool = false;
-    let mut redirect: bool = false;
-    let mut file_option: String = String::new();
-    let mut file_location: String = String::new();
-
-    let mut current_argument: String = String::new();
+use std::env;
+use std::ffi::{CStr, CString};
+
+use crate::dir_stack::{is_stack_prefix, stack_entry};
+
+pub type Redirects = Vec<(String, String)>;
+
+// whether the input is complete, the stages of its pipeline, whether output is redirected and where
+pub type Parsed = (bool, Vec<Vec<String>>, bool, Redirects);
+
+// operators and redirects the executor can run, the rest of what tokenize knows is refused
+pub const SUPPORTED_OPERATORS: [&str; 1] = ["|"];
+pub const SUPPORTED_REDIRECTS: [&str; 7] = [">", "1>", "2>", ">>", "1>>", "2>>", "<"];
+
+// reads the pipeline in `input` from the tokens the highlighter paints: its stages and
+// where their output goes. Whether more input is needed comes first, an operator the
+// executor cannot run is an error instead of turning into arguments
+pub fn input_parser(input: &str) -> Result<Parsed, String> {
+    let is_complete = !needs_more_input(input);
+    let tokens = tokenize(input).tokens;
+
     let mut arguments: Vec<String> = Vec::new();
     let mut arguments_list: Vec<Vec<String>> = Vec::new();
-    let mut redirects: Vec<(String, String)> = Vec::new();
+    let mut redirects: Redirects = Vec::new();
+    // where the last token ended, a newline after it ends the command
+    let mut previous_end = 0;
+
+    let mut tokens = tokens.iter().filter(|token| token.kind != TokenKind::Comment);
+    while let Some(token) = tokens.next() {
+        let text = &input[token.start..token.end];
+        // a newline right after `|` only continues the pipeline
+        if !arguments.is_empty() && input[previous_end..token.start].contains('\n') {
+            return Err("commands on separate lines are not supported".to_string());
+        }
+        previous_end = token.end;
+        match token.kind {
+            TokenKind::Word if compound_keyword(token).is_some_and(|word| COMPOUND_OPENERS.contains(&word) || COMPOUND_CLOSERS.contains(&word)) => {
+                return Err(format!("`{}` is not supported", token.text));
+            }
+            TokenKind::Word => {
+                // a backslash newline between words joins the lines and leaves nothing behind
+                if !token.text.is_empty() || !token.quoted.is_empty() {
+                    arguments.push(expand_word(input, token));
+                }
+            }
+            TokenKind::Operator if SUPPORTED_OPERATORS.contains(&text) => {
+                if arguments.is_empty() {
+                    return Err(format!("syntax error near unexpected token `{}'", text));
+                }
+                arguments_list.push(std::mem::take(&mut arguments));
+            }
+            TokenKind::Redirect if SUPPORTED_REDIRECTS.contains(&text) => {
+                let Some(target) = tokens.next().filter(|target| target.kind == TokenKind::Word) else {
+                    return Err("syntax error near unexpected token `newline'".to_string());
+                };
+                previous_end = target.end;
+                let location = expand_word(input, target);
+                match text {
+                    "1>" | ">" => redirects.push((location, "replace_output".to_string())),
+                    "1>>" | ">>" => redirects.push((location, "append_output".to_string())),
+                    "2>" => redirects.push((location, "replace_error".to_string())),
+                    "2>>" => redirects.push((location, "append_error".to_string())),
+                    // input is not redirected yet, the file goes to the command as an argument
+                    _ => arguments.push(location),
+                }
+            }
+            TokenKind::Operator | TokenKind::Redirect => {
+                return Err(format!("`{}` is not supported", text));
+            }
+            TokenKind::Comment | TokenKind::Heredoc => {}
+        }
+    }
+    if !arguments.is_empty() {
+        arguments_list.push(arguments);
+    }
 
-    let mut current_character = input.chars().peekable();
+    let redirect = !redirects.is_empty();
+    Ok((is_complete, arguments_list, redirect, redirects))
+}
 
-    let push_current_char = |current_argument: &mut String, arguments: &mut Vec<String>| {
-        if !current_argument.is_empty() {
-            arguments.push(current_argument.clone());
+// a word's text, with a leading `~` and the unquoted prefix up to the first `/` expanded
+fn expand_word(input: &str, token: &Token) -> String {
+    let raw = &input[token.start..token.end];
+    if let Some(rest) = raw.strip_prefix('~') {
+        let prefix_end = rest.find(['/', '\'', '"', '\\']).unwrap_or(rest.len());
+        let prefix = &rest[..prefix_end];
+        // `~"user"` and `~\user` stay as they are
+        let is_quoted = !rest[prefix_end..].is_empty() && !rest[prefix_end..].starts_with('/');
+        if !is_quoted && let Some(expanded) = expand_tilde(prefix) {
+            return format!("{}{}", expanded, &token.text[1 + prefix.len()..]);
         }
-        current_argument.clear();
-    };
+    }
+    token.text.clone()
+}
 
-    let _push_current_argument = |argument: &mut Vec<String> , argument_list: &mut Vec<Vec<String>> | {
-        if !argument.is_empty() {
-            argument_list.push(argument.clone());
+// what a piece of the line is to the highlighter and the parser
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Word,
+    // `|`, `||`, `&&`, `;` and `&`
+    Operator,
+    // `>`, `>>`, `1>`, `2>`, `2>>`, `<`, `<<`, `<<-` and `<<<`
+    Redirect,
+    Comment,
+    // the lines of a heredoc, its closing delimiter included
+    Heredoc,
+}
+
+// reserved words that leave the next word in command position
+const COMMAND_PREFIXES: [&str; 9] = ["if", "then", "else", "elif", "do", "while", "until", "{", "!"];
+// reserved words that open a compound command, and the ones that close it
+const COMPOUND_OPENERS: [&str; 7] = ["if", "case", "for", "select", "while", "until", "{"];
+const COMPOUND_CLOSERS: [&str; 4] = ["fi", "esac", "done", "}"];
+
+pub struct Token {
+    pub kind: TokenKind,
+    // byte range in the line, quotes and escapes included
+    pub start: usize,
+    pub end: usize,
+    // a word with its quotes and escapes taken out
+    pub text: String,
+    // first word of a pipeline stage, not after a redirect
+    pub command_position: bool,
+    // quoted parts of a word, quotes included, running to the end when left open
+    pub quoted: Vec<(usize, usize)>,
+    // `$NAME`, `${NAME}` and `$?` style references
+    pub variables: Vec<(usize, usize)>,
+}
+
+pub struct Tokens {
+    pub tokens: Vec<Token>,
+    // the quote left open at the end of the line
+    pub open_quote: Option<char>,
+    // the line ends in an unquoted backslash
+    pub trailing_escape: bool,
+    // a heredoc whose delimiter line has not come yet
+    pub open_heredoc: bool,
+    // a word started at the end of the line would be in command position
+    pub expects_command: bool,
+}
+
+impl Token {
+    fn new(kind: TokenKind, start: usize, end: usize) -> Self {
+        Token {
+            kind,
+            start,
+            end,
+            text: String::new(),
+            command_position: false,
+            quoted: Vec::new(),
+            variables: Vec::new(),
         }
-        argument.clear();
-    };
+    }
+}
+
+// where the variable reference whose `$` is at `start` ends, if it is one
+fn variable_end(line: &str, start: usize) -> Option<usize> {
+    let rest = &line[start + 1..];
+    let first = rest.chars().next()?;
+    if first == '{' {
+        return Some(rest.find('}').map_or(line.len(), |close| start + 1 + close + 1));
+    }
+    if matches!(first, '?' | '$' | '#' | '!' | '@' | '*' | '-') || first.is_ascii_digit() {
+        return Some(start + 2);
+    }
+    if first.is_alphabetic() || first == '_' {
+        let length = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        return Some(start + 1 + length);
+    }
+    None
+}
+
+// the delimiters of the heredocs among `tokens`, and whether leading tabs are stripped
+fn heredoc_delimiters(line: &str, tokens: &[Token]) -> Vec<(String, bool)> {
+    tokens
+        .windows(2)
+        .filter(|pair| pair[0].kind == TokenKind::Redirect && pair[1].kind == TokenKind::Word)
+        .filter_map(|pair| match &line[pair[0].start..pair[0].end] {
+            "<<" => Some((pair[1].text.clone(), false)),
+            "<<-" => Some((pair[1].text.clone(), true)),
+            _ => None,
+        })
+        .collect()
+}
 
-    while let Some(character) = current_character.next() {
-        if is_escaped {
-            current_argument.push(character);
-            is_escaped = false;
-            continue;
+// the end of the heredoc body starting at `start`, just past its delimiter line
+fn heredoc_end(line: &str, start: usize, delimiter: &str, strip_tabs: bool) -> Option<usize> {
+    let mut offset = start;
+    for body_line in line[start..].split_inclusive('\n') {
+        offset += body_line.len();
+        let text = body_line.strip_suffix('\n').unwrap_or(body_line);
+        let text = if strip_tabs { text.trim_start_matches('\t') } else { text };
+        if text == delimiter {
+            return Some(offset);
         }
-        match character {
-            '|' => {
-                if in_double_quotes || in_single_quotes || is_path {
-                    current_argument.push(character);
-                    continue;
-                }
+    }
+    None
+}
 
-                if let Some(&_next_char) = current_character.peek() {
-                    if _next_char == '|' {
-                        // || means logical OR for now treating as regular character
-                        current_argument.push(character);
-                        continue;
-                    }
-                }
+// splits a line into the tokens input_parser reads, but never fails: whatever is left open at
+// the end is reported instead, so half typed lines can be highlighted and checked
+pub fn tokenize(line: &str) -> Tokens {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut open_quote: Option<char> = None;
+    let mut trailing_escape = false;
+    let mut open_heredoc = false;
+    let mut expect_command = true;
+    let mut after_redirect = false;
+    let mut word: Option<Token> = None;
+    // tokens already checked for heredocs
+    let mut heredocs_seen = 0;
+    let mut chars = line.char_indices().peekable();
 
-                if !current_argument.is_empty() {
-                    push_current_char(&mut current_argument, &mut arguments);
+    // a finished word uses up the command position, or the redirect it belongs to
+    let finish_word = |word: &mut Option<Token>, tokens: &mut Vec<Token>, expect_command: &mut bool, after_redirect: &mut bool| {
+        if let Some(finished) = word.take() {
+            if *after_redirect {
+                *after_redirect = false;
+            } else if !(finished.command_position && COMMAND_PREFIXES.contains(&finished.text.as_str())) {
+                *expect_command = false;
+            }
+            tokens.push(finished);
+        }
+    };
+
+    while let Some((i, c)) = chars.next() {
+        let end = i + c.len_utf8();
+        match c {
+            // a newline ends the command like `;`, and starts the bodies of its heredocs
+            '\n' => {
+                finish_word(&mut word, &mut tokens, &mut expect_command, &mut after_redirect);
+                expect_command = true;
+                after_redirect = false;
+                let mut body_start = end;
+                for (delimiter, strip_tabs) in heredoc_delimiters(line, &tokens[heredocs_seen..]) {
+                    let body_end = heredoc_end(line, body_start, &delimiter, strip_tabs).unwrap_or_else(|| {
+                        open_heredoc = true;
+                        line.len()
+                    });
+                    tokens.push(Token::new(TokenKind::Heredoc, body_start, body_end));
+                    body_start = body_end;
+                }
+                heredocs_seen = tokens.len();
+                while chars.next_if(|&(j, _)| j < body_start).is_some() {}
+                continue;
+            }
+            c if c.is_whitespace() => {
+                finish_word(&mut word, &mut tokens, &mut expect_command, &mut after_redirect);
+                continue;
+            }
+            '#' if word.is_none() => {
+                let comment_end = line[i..].find('\n').map_or(line.len(), |newline| i + newline);
+                tokens.push(Token::new(TokenKind::Comment, i, comment_end));
+                while chars.next_if(|&(j, _)| j < comment_end).is_some() {}
+                continue;
+            }
+            '|' | '&' | ';' => {
+                finish_word(&mut word, &mut tokens, &mut expect_command, &mut after_redirect);
+                let mut end = end;
+                if c != ';' && chars.next_if(|&(_, next)| next == c).is_some() {
+                    end += 1;
                 }
-                _push_current_argument(&mut arguments, &mut arguments_list);
+                tokens.push(Token::new(TokenKind::Operator, i, end));
+                expect_command = true;
+                after_redirect = false;
+                continue;
             }
-            '<' => {
-                if in_double_quotes || in_single_quotes {
-                    current_argument.push(character);
-                } else {
-                    if !current_argument.is_empty() {
-                        push_current_char(&mut current_argument, &mut arguments);
+            '<' | '>' => {
+                // `1>` and `2>` take the digit right before them along
+                let mut start = i;
+                if c == '>'
+                    && let Some(digit) = &word
+                    && digit.end == i
+                    && digit.quoted.is_empty()
+                    && matches!(&line[digit.start..i], "1" | "2")
+                {
+                    start = digit.start;
+                    word = None;
+                }
+                finish_word(&mut word, &mut tokens, &mut expect_command, &mut after_redirect);
+                let mut end = end;
+                if chars.next_if(|&(_, next)| next == c).is_some() {
+                    end += 1;
+                    // `<<-` and `<<<`
+                    if c == '<' && chars.next_if(|&(_, next)| next == '-' || next == '<').is_some() {
+                        end += 1;
                     }
-                    is_path = true;
                 }
+                tokens.push(Token::new(TokenKind::Redirect, start, end));
+                after_redirect = true;
+                continue;
             }
-            '\\' => {
-                if in_single_quotes {
-                    current_argument.push(character);
-                    continue;
-                } else if in_double_quotes {
-                    if let Some(&next_character) = current_character.peek() {
-                        if matches!(next_character, '\\' | '"' | '$' | '`') {
-                            current_character.next();
-                            if next_character != '\n' {
-                                current_argument.push(next_character);
+            _ => {}
+        }
+
+        let current = word.get_or_insert_with(|| {
+            let mut token = Token::new(TokenKind::Word, i, i);
+            token.command_position = expect_command && !after_redirect;
+            token
+        });
+        match c {
+            '\\' => match chars.next() {
+                // a backslash newline joins the lines
+                Some((_, '\n')) => {}
+                Some((_, next)) => current.text.push(next),
+                None => trailing_escape = true,
+            },
+            '\'' | '"' => {
+                let mut closed = false;
+                while let Some((j, d)) = chars.next() {
+                    if d == c {
+                        closed = true;
+                        current.quoted.push((i, j + 1));
+                        break;
+                    }
+                    if c == '"' && d == '\\' {
+                        match chars.peek() {
+                            Some(&(_, next)) if matches!(next, '\\' | '"' | '$' | '`') => {
+                                current.text.push(next);
+                                chars.next();
+                            }
+                            // a backslash newline joins the lines inside double quotes too
+                            Some(&(_, '\n')) => {
+                                chars.next();
                             }
-                        } else {
-                            current_argument.push(character);
+                            _ => current.text.push(d),
                         }
-                    } else {
-                        current_argument.push(character);
                         continue;
                     }
-                } else {
-                    is_escaped = true;
-                    continue;
+                    if c == '"'
+                        && d == '$'
+                        && let Some(variable_end) = variable_end(line, j)
+                    {
+                        current.variables.push((j, variable_end));
+                    }
+                    current.text.push(d);
                 }
-            }
-            '\'' => {
-                if in_double_quotes {
-                    current_argument.push(character);
-                    continue;
-                } else {
-                    in_single_quotes = !in_single_quotes;
-                    is_complete = !is_complete;
-                    continue;
+                if !closed {
+                    current.quoted.push((i, line.len()));
+                    open_quote = Some(c);
                 }
             }
-
-            '\"' => {
-                if in_single_quotes {
-                    current_argument.push(character);
-                    continue;
-                } else {
-                    in_double_quotes = !in_double_quotes;
-                    is_complete = !is_complete;
-                    continue;
+            '$' => {
+                if let Some(variable_end) = variable_end(line, i) {
+                    current.variables.push((i, variable_end));
                 }
+                current.text.push(c);
             }
+            _ => current.text.push(c),
+        }
+        current.end = chars.peek().map_or(line.len(), |&(next, _)| next);
+    }
+    finish_word(&mut word, &mut tokens, &mut expect_command, &mut after_redirect);
+    // heredocs on the last line, their bodies have not started
+    if !heredoc_delimiters(line, &tokens[heredocs_seen..]).is_empty() {
+        open_heredoc = true;
+    }
 
-            c if c.is_whitespace() => {
-                if in_double_quotes || in_single_quotes {
-                    current_argument.push(character);
-                    continue;
-                } else {
-                    if !current_argument.is_empty() {
-                        push_current_char(&mut current_argument, &mut arguments);
-                        continue;
-                    }
-                }
+    Tokens {
+        tokens,
+        open_quote,
+        trailing_escape,
+        open_heredoc,
+        expects_command: expect_command && !after_redirect,
+    }
+}
+
+// whether `input` needs more lines before it is a whole command: an open quote or heredoc,
+// a trailing `\`, `|`, `&&` or `||`, or a compound command not closed yet. Whether it can
+// run is up to input_parser
+pub fn needs_more_input(input: &str) -> bool {
+    let tokens = tokenize(input);
+    if tokens.open_quote.is_some() || tokens.trailing_escape || tokens.open_heredoc {
+        return true;
+    }
+    let ends_in_operator = tokens
+        .tokens
+        .iter()
+        .rfind(|token| token.kind != TokenKind::Comment)
+        .is_some_and(|last| last.kind == TokenKind::Operator && matches!(&input[last.start..last.end], "|" | "&&" | "||"));
+    ends_in_operator || compound_depth(&tokens.tokens) > 0
+}
+
+// the reserved word starting a compound command, when `token` is one
+fn compound_keyword(token: &Token) -> Option<&str> {
+    let is_reserved = token.kind == TokenKind::Word && token.command_position && token.quoted.is_empty();
+    is_reserved.then_some(token.text.as_str())
+}
+
+// how many compound commands are open at the end of `tokens`
+fn compound_depth(tokens: &[Token]) -> i32 {
+    tokens
+        .iter()
+        .filter_map(compound_keyword)
+        .map(|word| {
+            if COMPOUND_OPENERS.contains(&word) {
+                1
+            } else if COMPOUND_CLOSERS.contains(&word) {
+                -1
+            } else {
+                0
             }
+        })
+        .sum()
+}
 
-            _ => {
-                if character == '>' {
-                    if is_path {
-                        continue;
-                    }
+// expands the part after `~` (without the slash), None means leave the word alone
+pub fn expand_tilde(prefix: &str) -> Option<String> {
+    match prefix {
+        "" => env::var("HOME").ok().or_else(|| {
+            env::home_dir().map(|path| path.display().to_string())
+        }),
+        "+" => env::var("PWD").ok(),
+        "-" => env::var("OLDPWD").ok(),
+        _ if is_stack_prefix(prefix) => stack_entry(prefix),
+        user => home_dir_of(user),
+    }
+}
 
-                    if in_double_quotes || in_single_quotes {
-                        current_argument.push(character);
-                        continue;
-                    }
-                    let mut prefix = String::new();
-                    if current_argument == "1" || current_argument == "2" {
-                        prefix = current_argument.clone();
-                        current_argument.clear();
-                    }
+fn home_dir_of(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 4096];
 
-                    if !current_argument.is_empty() {
-                        push_current_char(&mut current_argument, &mut arguments);
-                    }
+    // SAFETY: all pointers point into buffers that outlive the call
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() || entry.pw_dir.is_null() {
+        return None;
+    }
 
-                    current_argument = prefix;
-                    current_argument.push(character);
+    // SAFETY: pw_dir is a nul terminated string inside `buffer`
+    let dir = unsafe { CStr::from_ptr(entry.pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
+}
 
-                    while let Some(&next_char) = current_character.peek() {
-                        if next_char == '>' {
-                            current_argument.push(current_character.next().unwrap());
-                        } else {
-                            break;
-                        }
-                    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                    push_current_char(&mut current_argument, &mut arguments);
-                    continue;
-                }
+    // joins `lines` the way the editor does, checking each one but the last asks for more
+    fn joined(lines: &[&str]) -> String {
+        let mut buffer = lines[0].to_string();
+        for line in &lines[1..] {
+            assert!(needs_more_input(&buffer), "{:?} should continue", buffer);
+            buffer.push('\n');
+            buffer.push_str(line);
+        }
+        assert!(!needs_more_input(&buffer), "{:?} should be complete", buffer);
+        buffer
+    }
 
-                if character == '-' {
-                    if in_double_quotes || in_single_quotes {
-                        current_argument.push(character);
-                        continue;
-                    }
-                    if let Some(&next_char) = current_character.peek() {
-                        if matches!(next_char, 'r' | 'w' | 'a') {
-                            if !current_argument.is_empty() {
-                                push_current_char(&mut current_argument, &mut arguments);
-                            }
+    fn stages(input: &str) -> Vec<Vec<String>> {
+        let (is_complete, stages, _, _) = input_parser(input).unwrap();
+        assert!(is_complete);
+        stages
+    }
 
-                            current_character.next();
+    fn words(stage: &[&str]) -> Vec<String> {
+        stage.iter().map(|word| word.to_string()).collect()
+    }
 
-                            current_argument.push('-');
-                            current_argument.push(next_char);
+    #[test]
+    fn open_quotes_keep_the_newline() {
+        let buffer = joined(&["echo \"one", "two\" 'three", "four'"]);
+        assert_eq!(stages(&buffer), vec![words(&["echo", "one\ntwo", "three\nfour"])]);
+    }
 
-                            push_current_char(&mut current_argument, &mut arguments);
-                            continue;
-                        }
-                    }
-                }
-                current_argument.push(character);
-            }
+    #[test]
+    fn trailing_backslash_joins_the_lines() {
+        assert_eq!(stages(&joined(&["echo one \\", "two"])), vec![words(&["echo", "one", "two"])]);
+        assert_eq!(stages(&joined(&["echo one\\", "two"])), vec![words(&["echo", "onetwo"])]);
+        assert_eq!(stages(&joined(&["echo \"one\\", "two\""])), vec![words(&["echo", "onetwo"])]);
+    }
+
+    #[test]
+    fn backslash_newline_stays_inside_single_quotes() {
+        let buffer = joined(&["echo 'one\\", "two'"]);
+        assert_eq!(stages(&buffer), vec![words(&["echo", "one\\\ntwo"])]);
+    }
+
+    #[test]
+    fn trailing_pipe_continues_the_pipeline() {
+        let buffer = joined(&["ls -a |", "  # counted below", "wc -l > out"]);
+        let (_, stages, redirect, redirects) = input_parser(&buffer).unwrap();
+        assert_eq!(stages, vec![words(&["ls", "-a"]), words(&["wc", "-l"])]);
+        assert!(redirect);
+        assert_eq!(redirects, vec![("out".to_string(), "replace_output".to_string())]);
+    }
+
+    #[test]
+    fn lists_heredocs_and_compound_commands_continue() {
+        let cases: [(&[&str], &str); 8] = [
+            (&["true &&", "false"], "`&&` is not supported"),
+            (&["false ||", "  # a comment", "true"], "`||` is not supported"),
+            (&["cat <<EOF", "one", "EOF"], "`<<` is not supported"),
+            (&["cat <<-'END' | wc", "\tEND"], "`<<-` is not supported"),
+            (&["if true", "then echo yes", "fi"], "`if` is not supported"),
+            (&["for x in a b", "do", "  while true; do", "  done", "done"], "`for` is not supported"),
+            (&["case $x in", "a) echo a ;;", "esac"], "`case` is not supported"),
+            (&["{ echo", "}"], "`{` is not supported"),
+        ];
+        for (lines, error) in cases {
+            assert_eq!(input_parser(&joined(lines)).err().as_deref(), Some(error), "{:?}", lines);
         }
     }
 
-    // final check
-    if !current_argument.is_empty() {
-        push_current_char(&mut current_argument, &mut arguments);
+    #[test]
+    fn reserved_words_elsewhere_do_not_continue() {
+        for line in ["echo if for {", "'if' true", "echo done", "echo '<<EOF'"] {
+            assert!(!needs_more_input(line), "{:?} should be complete", line);
+        }
+        assert_eq!(stages("echo if for {"), vec![words(&["echo", "if", "for", "{"])]);
     }
-    
-    _push_current_argument(&mut arguments, &mut arguments_list);
-
-    if let Some(last_command) = arguments_list.last_mut() {
-        let mut i = 0;
-        while i < last_command.len() {
-            if matches!(
-                last_command[i].trim(),
-                ">" | "1>" | "2>" | ">>" | "1>>" | "2>>"
-            ) {
-                let redirect_type = last_command.remove(i);
-                if i < last_command.len() {
-                    let redirect_location = last_command.remove(i);
-
-                    match redirect_type.trim() {
-                        "1>" | ">" => {
-                            redirects.push((redirect_location, "replace_output".to_string()));
-                        }
-                        "1>>" | ">>" => {
-                            redirects.push((redirect_location, "append_output".to_string()));
-                        }
-                        "2>" => {
-                            redirects.push((redirect_location, "replace_error".to_string()));
-                        }
-                        "2>>" => {
-                            redirects.push((redirect_location, "append_error".to_string()));
-                        }
-                        _ => {}
-                    }
-                }
-            } else if matches!(last_command[i].trim(), "-r" | "-w" | "-a") {
-                let result = last_command.remove(i);
-                if i < last_command.len() {
-                    let file_result = last_command.remove(i);
-                    match result.trim() {
-                        "-a" => {
-                            file_option = "append".into();
-                            file_location = file_result.into();
-                        }
-                        "-r" => {
-                            file_option = "read".into();
-                            file_location = file_result.into();
-                        }
-                        "-w" => {
-                            file_option = "write".into();
-                            file_location = file_result.into();
-                        }
-                        _ => {}
-                    }
-                }
-            } else {
-                i += 1;
-            }
+
+    #[test]
+    fn unsupported_operators_are_errors() {
+        for input in ["true && false", "true || false", "echo a; echo b", "sleep 1 &", "cat <<EOF\nhi\nEOF"] {
+            assert!(input_parser(input).is_err(), "{:?} should be refused", input);
         }
     }
 
-    if !redirects.is_empty() {
-        redirect = true;
+    #[test]
+    fn commands_on_separate_lines_are_errors() {
+        assert!(input_parser("echo one\necho two").is_err());
+        assert_eq!(stages("echo one # comment\n"), vec![words(&["echo", "one"])]);
     }
-    return (
-        is_complete,
-        arguments_list,
-        redirect,
-        redirects,
-        file_location,
-        file_option,
-    );
-}
\ No newline at end of file
+}
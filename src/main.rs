@@ -1,82 +1,102 @@
-use std::{env, fs};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 #[allow(unused_imports)]
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::Path;
+use std::time::Instant;
 use anyhow::Result;
-use is_executable::IsExecutable;
-use rustyline::Config;
+use rustyline::{Cmd, Config, ConditionalEventHandler, Event, EventContext, EventHandler, KeyEvent, RepeatCount};
+use rustyline::highlight::CmdKind;
+use rustyline::history::{History, SearchDirection};
+use rustyline::validate::{ValidationContext, ValidationResult};
 use rustyline::completion::Pair;
 use rustyline::error::ReadlineError;
 use rustyline::{Editor, completion::Completer};
-use rustyline::history::{History, FileHistory};
-use std::str::FromStr;
-use std::fs::OpenOptions;
 
 mod input_parser;
-use input_parser::input_parser;
+use input_parser::{input_parser, needs_more_input};
 
 mod handler;
-use handler::{redirect_handler, echo_handler, type_handler, cd_handler, general_handler, pwd_handler, execute_pipeline,  SHELL_COMMANDS};
 
-pub struct MyHelper {
-    completions: Vec<String>
-}
+mod dir_stack;
+use dir_stack::{dirs_handler, popd_handler, pushd_handler};
 
-impl MyHelper {
-    fn new() -> Self {
-        let mut shell_map : Vec<String> = Vec::new();
-        for command in SHELL_COMMANDS {
-            let result = format!("{} ",command);
-            shell_map.push(result.into());
-        }
+mod xdg;
 
-        Self {
-            completions: shell_map
-        }
-    }
+mod frecency;
+use frecency::{frecency_candidates, z_handler};
 
-    fn add_entry(&mut self, v:&str) {
-        self.completions.push(v.into());
-    }
+mod shopt;
+use shopt::{shopt_enabled, shopt_handler};
 
-    fn add_path_completions (&mut self, path:PathBuf) {
-        if !path.exists() {
-            return;
-        }
+mod spelling;
 
-        let files_search_result: std::result::Result<fs::ReadDir, io::Error> = fs::read_dir(path);
-        match files_search_result {
-            Ok(files) => {
-                let files_in_path = files;
-                for file in files_in_path {
-                    match file {
-                        Ok(entry) =>{
-                            if entry.path().is_dir() {
-                                continue;
-                            }
-                            if entry.path().is_file() && entry.path().is_executable() {
-                                if let Some(filename) = entry.path().file_name().and_then(|n| n.to_str()){
-                                    self.add_entry(&format!("{} ",filename));
-                                }
-                            }
-                        }
-                        Err(_) => {
-                            return;
-                        }
-                    }
-                }
-            }
-            Err(_) => {
-                return;
-            }
-            }
-    }
+mod glob;
+
+mod history;
+use history::{add_history, append_history_on_exit, apply_history_settings, history_handler, init_history_file, is_saving_history, load_history_file, share_history, ShellHistory};
+
+mod history_expansion;
+use history_expansion::{expand_history, needs_expansion};
+
+mod history_store;
+use history_store::{current_directory, record_command};
+
+mod history_picker;
+use history_picker::{set_picker_history, HistoryPicker};
+
+mod fc;
+use fc::fc_handler;
+
+mod command_cache;
+use command_cache::{command_extending, command_names, find_command, hash_handler, scan_path};
+
+mod highlight;
+use highlight::{highlight_line, Colors};
+
+mod completion;
+use completion::{path_candidates, quote_word, word_at, PathFilter};
+
+mod programmable;
+use programmable::{compgen_handler, complete_handler, spec_candidates};
+
+mod git;
+mod git_objects;
+mod git_prompt;
+mod sha1;
+
+mod providers;
+use providers::provider_candidates;
+
+mod prompt;
+use prompt::{collapse_prompt, continuation_prompt, primary_prompt, right_prompt, right_prompt_sequence, set_last_duration, text_width, trace_prompt, transient_prompt, Prompt, PromptCounts};
+
+mod time_format;
+use time_format::now_epoch;
+use handler::{redirect_handler, echo_handler, type_handler, cd_handler, general_handler, pwd_handler, execute_pipeline, init_pwd, last_status, set_last_status};
+
+#[derive(Default)]
+pub struct MyHelper {
+    // off for the final redraw of a line, so the accepted line shows no matching pair
+    show_match: Cell<bool>,
+    // the prompt being read with, drawn with its `\[...\]` parts
+    prompt: RefCell<Prompt>,
+    // RPROMPT for the line being read
+    right: RefCell<Prompt>,
+    // columns of the suggestion after the line, which the right prompt has to leave room for
+    hint_width: Cell<usize>,
 }
 
-impl Default for MyHelper {
-    fn default() -> Self {
-        Self::new()
+// reads a line showing `prompt`, rustyline measures the plain text and draws the styled one.
+// `right` goes at the other end of the line
+fn read_line(readline: &mut Editor<MyHelper, ShellHistory>, prompt: Prompt, right: Prompt) -> rustyline::Result<String> {
+    let plain = prompt.plain.clone();
+    if let Some(helper) = readline.helper() {
+        helper.prompt.replace(prompt);
+        helper.right.replace(right);
     }
+    readline.readline(&plain)
 }
 
 impl rustyline::Helper for MyHelper {}
@@ -90,46 +110,175 @@ impl Completer for MyHelper {
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
         let prefix: &str = &line[..pos];
         let mut matches: Vec<Pair> = Vec::new();
-        for trigger in &self.completions {
-            if trigger.starts_with(prefix) {
+        let context = word_at(line, pos);
+
+        // `z` keywords complete to the directories they would jump to
+        if let Some(keywords) = prefix.strip_prefix("z ") {
+            let keywords: Vec<String> = keywords.split_whitespace().map(String::from).collect();
+            if !keywords.is_empty() {
+                for candidate in frecency_candidates(&keywords) {
+                    matches.push(Pair {
+                        replacement: quote_word(&candidate, None),
+                        display: candidate,
+                    });
+                }
+                return Ok(("z ".len(), matches));
+            }
+        }
+        // a spec registered with `complete` goes first, then the native providers, filenames
+        // are the fallback
+        if !context.command_position
+            && let Some(candidates) = spec_candidates(&context, line, pos)
+        {
+            return Ok((context.start, candidates));
+        }
+        if !context.command_position
+            && let Some(candidates) = provider_candidates(&context)
+        {
+            return Ok((context.start, candidates));
+        }
+        // commands in command position, unless they are typed as a path
+        if !context.command_position || context.word.contains('/') {
+            let filter = if context.command_position { PathFilter::Executables } else { PathFilter::All };
+            return Ok((context.start, path_candidates(&context, filter)));
+        }
+        for name in command_names() {
+            if name.starts_with(&context.word) {
                 matches.push(Pair {
-                    display: trigger.clone().trim_end().to_string(),
-                    replacement: trigger.clone()
+                    replacement: quote_word(&name, context.quote) + " ",
+                    display: name,
                 });
             }
         }
         matches.sort_by(|a, b| a.display.cmp(&b.display));
-        
-        Ok((pos-prefix.len(), matches))
+        matches.dedup_by(|a, b| a.display == b.display);
+
+        Ok((context.start, matches))
     }
 }
 
 impl rustyline::hint::Hinter for MyHelper {
     type Hint = String;
     fn hint(&self, line: &str, pos: usize, ctx: &rustyline::Context<'_>) -> Option<Self::Hint> {
-        if !line.is_empty() {
-            if let Ok((_, c)) = self.complete(line, pos, ctx) {
-                if let Some(e) = c.get(0) {
-                    return Some(e.display[pos..].to_string());
-                }
-            }
+        let hint = self.suggestion(line, pos, ctx);
+        self.hint_width.set(hint.as_deref().map_or(0, text_width));
+        hint
+    }
+}
+
+impl MyHelper {
+    // fish-style suggestions: the newest history entry starting with the line, else the first command name
+    fn suggestion(&self, line: &str, pos: usize, ctx: &rustyline::Context<'_>) -> Option<String> {
+        if line.is_empty() || pos < line.len() {
+            return None;
+        }
+        let history = ctx.history();
+        let newest = if ctx.history_index() == history.len() {
+            ctx.history_index().saturating_sub(1)
+        } else {
+            ctx.history_index()
+        };
+        if let Ok(Some(found)) = history.starts_with(line, newest, SearchDirection::Reverse)
+            && let Some(rest) = found.entry.strip_prefix(line)
+            && !rest.is_empty()
+        {
+            return Some(rest.to_string());
+        }
+        // only command names after that, the hinter runs on every key and a full completion
+        // could start `complete -C` programs or reread ssh and git files each time
+        let context = word_at(line, pos);
+        if context.command_position
+            && context.quote.is_none()
+            && !context.word.is_empty()
+            && !context.word.contains('/')
+            && line[context.start..] == context.word
+        {
+            return command_extending(&context.word).map(|name| name[context.word.len()..].to_string());
         }
         None
     }
 }
-impl rustyline::highlight::Highlighter for MyHelper {}
-impl rustyline::validate::Validator for MyHelper {}
+impl rustyline::highlight::Highlighter for MyHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let painted = highlight_line(line, pos, self.show_match.get());
+        // the right prompt stays on single lines that leave room for it
+        let right = if line.contains('\n') {
+            String::new()
+        } else {
+            let prompt = self.prompt.borrow();
+            let prompt_width = prompt.plain.rsplit('\n').next().map_or(0, text_width);
+            right_prompt_sequence(&self.right.borrow(), prompt_width + text_width(line) + self.hint_width.get())
+        };
+        match painted {
+            Some(painted) => Cow::Owned(painted + &right),
+            None if right.is_empty() => Cow::Borrowed(line),
+            None => Cow::Owned(format!("{}{}", line, right)),
+        }
+    }
+
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, default: bool) -> Cow<'b, str> {
+        let current = self.prompt.borrow();
+        if default && current.plain == prompt {
+            Cow::Owned(current.styled.clone())
+        } else {
+            Cow::Borrowed(prompt)
+        }
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        match Colors::from_env() {
+            Some(colors) => Cow::Owned(colors.paint("hint", hint)),
+            None => Cow::Borrowed(hint),
+        }
+    }
+
+    // every character can change the colours, so the line is always redrawn
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        // a forced refresh drops the hint without asking the hinter
+        if kind == CmdKind::ForcedRefresh {
+            self.hint_width.set(0);
+        }
+        self.show_match.set(kind != CmdKind::ForcedRefresh);
+        kind != CmdKind::ForcedRefresh
+    }
+}
+// Enter inserts a newline until the parser has a whole command, which then runs and is
+// saved as one history entry
+impl rustyline::validate::Validator for MyHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if needs_more_input(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+// Ctrl-F takes the suggestion like Right-arrow does, anywhere else it still moves a character
+struct AcceptSuggestion;
+
+impl ConditionalEventHandler for AcceptSuggestion {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        if ctx.has_hint() && ctx.pos() == ctx.line().len() {
+            Some(Cmd::CompleteHint)
+        } else {
+            None
+        }
+    }
+}
 
 fn main() {
-    let mut complete: bool = true;
     let mut readline: Editor<MyHelper, _>;
-    let mut my_helper = MyHelper::default();
     let mut last_entry: usize = 0;
-    let config = Config::builder()
+    let mut pending: VecDeque<String> = VecDeque::new();
+    // command lines run so far, for `\#` in the prompt
+    let mut commands_run: usize = 0;
+    let config = apply_history_settings(Config::builder())
         .completion_type(rustyline::CompletionType::List)
         .build();
-    let readline_result: Result<Editor<MyHelper, rustyline::history::FileHistory>, ReadlineError> =
-        Editor::with_config(config);
+    let history = ShellHistory::with_config(&config);
+    let readline_result: Result<Editor<MyHelper, ShellHistory>, ReadlineError> =
+        Editor::with_history(config, history);
     match readline_result {
         Ok(result) => {
             readline = result;
@@ -140,84 +289,177 @@ fn main() {
         }
     }
 
-    if let Ok(path_var) = std::env::var("PATH") {
-        let paths = std::env::split_paths(&path_var);
-
-        for path in paths {
-            my_helper.add_path_completions(path);
-        }
-    }
-
-    readline.set_helper(Some(my_helper));
-    if let Some(location) = env::var_os("HISTFILE") {
-        let path = PathBuf::from(location);
-
-        if let Ok(contents) = std::fs::read_to_string(&path) {
-            for line in contents.lines() {
-                let _ = readline.add_history_entry(line);
-            }
-
-            last_entry = readline.history().len();
-        }
+    scan_path();
+    readline.set_helper(Some(MyHelper::default()));
+    readline.bind_sequence(KeyEvent::ctrl('R'), EventHandler::Conditional(Box::new(HistoryPicker)));
+    readline.bind_sequence(KeyEvent::ctrl('F'), EventHandler::Conditional(Box::new(AcceptSuggestion)));
+    init_pwd();
+    if let Some(path) = init_history_file() {
+        let _ = load_history_file(&mut readline, &path, &mut last_entry);
     }
     loop {
         let mut _args: Vec<String> = Vec::new();
-        let input = readline.readline(if complete { "$ " } else { "> " });
+        // lines queued by `fc` run before the next prompt, shown as they start like in bash
+        let queued = pending.pop_front();
+        if queued.is_none() && shopt_enabled("histshare") {
+            share_history(&mut readline, &mut last_entry);
+        }
+        let is_queued = queued.is_some();
+        let input = match queued {
+            Some(line) => {
+                println!("{}", line);
+                Ok(line)
+            }
+            None => {
+                let counts = PromptCounts {
+                    history_number: readline.history().len() + 1,
+                    command_number: commands_run + 1,
+                };
+                set_picker_history(readline.history().timed_entries());
+                let prompt = primary_prompt(&counts);
+                let shown = prompt.plain.clone();
+                let result = read_line(&mut readline, prompt, right_prompt(&counts));
+                if let Ok(line) = &result
+                    && shopt_enabled("transientprompt")
+                {
+                    collapse_prompt(&shown, line, &transient_prompt(&counts));
+                }
+                result
+            }
+        };
         match input {
-            Ok(line) => {
-                let history = readline.add_history_entry(line.as_str());
-                match history {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error while keeping history: {}", e);
+            Ok(mut line) => {
+                // a queued line can stop short, the rest is asked for with PS2 like bash does
+                while needs_more_input(&line) {
+                    let counts = PromptCounts {
+                        history_number: readline.history().len() + 1,
+                        command_number: commands_run + 1,
+                    };
+                    match read_line(&mut readline, continuation_prompt(&counts), Prompt::default()) {
+                        Ok(more) => {
+                            line.push('\n');
+                            line.push_str(&more);
+                        }
+                        Err(_) => break,
+                    }
+                }
+                let mut print_only = false;
+                let line = if !is_queued && needs_expansion(&line) {
+                    let entries: Vec<String> = readline.history().iter().cloned().collect();
+                    match expand_history(&line, &entries) {
+                        Ok(expansion) => {
+                            // the expanded line is echoed, like bash does
+                            if expansion.expanded {
+                                println!("{}", expansion.line);
+                            }
+                            print_only = expansion.print_only;
+                            expansion.line
+                        }
+                        Err(msg) => {
+                            println!("{}", msg);
+                            continue;
+                        }
                     }
+                } else {
+                    line
+                };
+
+                let is_recorded = add_history(&mut readline, &line, &mut last_entry) && is_saving_history();
+                if print_only {
+                    continue;
                 }
                 if line.trim().is_empty() {
                     println!("line is empty");
                     continue;
                 } else {
-                    let (is_complete, results, redirect, redirects, file_location, file_option) =
-                        input_parser(&line);
-                    complete = is_complete;
+                    let (is_complete, results, redirect, redirects) = match input_parser(&line) {
+                        Ok(parsed) => parsed,
+                        Err(msg) => {
+                            println!("{}", msg);
+                            set_last_status(2);
+                            continue;
+                        }
+                    };
 
-                    if complete && !results.is_empty() {
+                    if is_complete && !results.is_empty() {
+                        let started_at = now_epoch();
+                        let timer = Instant::now();
+                        let cwd = current_directory();
+                        set_last_status(0);
+                        commands_run += 1;
+                        if shopt_enabled("xtrace") {
+                            let counts = PromptCounts {
+                                history_number: readline.history().len(),
+                                command_number: commands_run,
+                            };
+                            let trace = trace_prompt(&counts);
+                            for stage in &results {
+                                eprintln!("{}{}", trace.styled, stage.join(" "));
+                            }
+                        }
 
                         if results.len() > 1 {
                             execute_pipeline(&results, redirect, redirects, &mut last_entry);
-                        } else if let Some(command_args) = results.first() {
-                            if !command_args.is_empty() {
-                                let command = command_args[0].clone();
-                                _args.extend(command_args[1..].to_vec());
+                        } else if let Some(command_args) = results.first()
+                            && !command_args.is_empty()
+                        {
+                            let command = command_args[0].clone();
+                            _args.extend(command_args[1..].to_vec());
 
-                                if redirect {
-                                    redirect_handler(redirects.clone());
-                                }
-                                match command.trim() {
-                                    "" => print!(""),
-                                    "exit" => {
-                                        append_history_on_exit(&mut readline, &mut last_entry);
-                                        break;
-                                    }
-                                    "echo" => echo_handler(&_args, redirect, redirects),
-                                    "type" => type_handler(&_args, redirect, redirects),
-                                    "pwd" => pwd_handler(&_args, &command, redirect, redirects),
-                                    "cd" => cd_handler(&_args, &command),
-                                    "history" => history_handler(
-                                        &mut readline,
-                                        &_args,
-                                        &file_option,
-                                        &file_location,
-                                        &mut last_entry,
-                                    ),
-                                    _ => general_handler(&_args, &command, redirect, redirects),
+                            if redirect {
+                                redirect_handler(redirects.clone());
+                            }
+                            match command.trim() {
+                                "" => print!(""),
+                                "exit" => break,
+                                "echo" => echo_handler(&_args, redirect, redirects),
+                                "type" => type_handler(&_args, redirect, redirects),
+                                "pwd" => pwd_handler(&_args, &command, redirect, redirects),
+                                "cd" => cd_handler(&_args, &command),
+                                "pushd" => pushd_handler(&_args, &command, redirect, redirects),
+                                "popd" => popd_handler(&_args, &command, redirect, redirects),
+                                "dirs" => dirs_handler(&_args, &command, redirect, redirects),
+                                "z" => z_handler(&_args, &command, redirect, redirects),
+                                "shopt" => shopt_handler(&_args, &command, redirect, redirects),
+                                "complete" => complete_handler(&_args, &command, redirect, redirects),
+                                "compgen" => compgen_handler(&_args, &command, redirect, redirects),
+                                "hash" => hash_handler(&_args, &command, redirect, redirects),
+                                "fc" => pending.extend(fc_handler(
+                                    &mut readline,
+                                    &_args,
+                                    &command,
+                                    redirect,
+                                    redirects,
+                                    &mut last_entry,
+                                )),
+                                "history" => history_handler(
+                                    &mut readline,
+                                    &_args,
+                                    &command,
+                                    redirect,
+                                    redirects,
+                                    &mut last_entry,
+                                ),
+                                // autocd: a bare directory name that is not a command is a cd
+                                dir if shopt_enabled("autocd")
+                                    && _args.is_empty()
+                                    && find_command(dir).is_none()
+                                    && Path::new(dir).is_dir() =>
+                                {
+                                    println!("cd -- {}", dir);
+                                    cd_handler(&[dir.to_string()], "cd");
                                 }
+                                _ => general_handler(&_args, &command, redirect, redirects),
                             }
                         }
+                        set_last_duration(timer.elapsed());
+                        if is_recorded {
+                            record_command(line.trim(), &cwd, last_status(), started_at, timer.elapsed());
+                        }
                     }
                 }
             }
             Err(ReadlineError::Interrupted) => {
-                let _ = readline.clear_history();
                 println!("^C");
                 break;
             }
@@ -231,123 +473,5 @@ fn main() {
             }
         }
     }
+    append_history_on_exit(&mut readline, &mut last_entry);
 }
-
-
-fn history_handler(
-    readline: &mut Editor<MyHelper, FileHistory>,
-    args: &Vec<String>,
-    file_option: &String,
-    file_location: &String,
-    last_entry: &mut usize,
-) {
-    if file_option.is_empty() && file_location.is_empty() {
-        if args.len() == 0 {
-            for (i, entry) in readline.history().iter().enumerate() {
-                println!("    {}  {}", i + 1, entry);
-            }
-            return;
-        }
-        if args.len() > 1 {
-            println!("Too many arguments provided");
-            return;
-        }
-        let limit_result: Result<usize, <usize as FromStr>::Err> = args[0].parse();
-        match limit_result {
-            Ok(limit) => {
-                let lenght = readline.history().len();
-                let start_index = lenght.saturating_sub(limit);
-
-                for (i, entry) in readline.history().iter().enumerate().skip(start_index) {
-                    println!("    {}  {}", i + 1, entry);
-                }
-                return;
-            }
-            Err(_) => {
-                println!("{}: provide correct arguments for command", args[0]);
-                return;
-            }
-        }
-    } else {
-        // println!("{}   {}", file_location,file_option);
-        match file_option.trim() {
-            "read" => {
-                let result_file = OpenOptions::new().read(true).open(file_location);
-                match result_file {
-                    Ok(_) => {
-                        let result = readline.load_history(file_location);
-                        match result {
-                            Ok(_) => {}
-                            Err(e) => {
-                                println!("Sorry could not load history from file {}", e);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        println!("Sorry erorred out {}", e);
-                        return;
-                    }
-                }
-            }
-            "write" => {
-                let result_file = OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .truncate(true)
-                    .create(true)
-                    .open(file_location);
-                match result_file {
-                    Ok(mut file) => {
-                        for entry in readline.history().iter() {
-                            let _ = writeln!(file, "{}", entry);
-                        }
-                    }
-                    Err(e) => {
-                        println!("Sorry could not load file {}", e);
-                    }
-                }
-            }
-
-            "append" => {
-                let result_file = OpenOptions::new()
-                    .read(true)
-                    .append(true)
-                    .write(true)
-                    .create(true)
-                    .open(file_location);
-                match result_file {
-                    Ok(mut file) => {
-                        for entry in readline.history().iter().skip(*last_entry) {
-                            let _ = writeln!(file, "{}", entry);
-                        }
-                        *last_entry = readline.history().len();
-                    }
-                    Err(e) => {
-                        println!("Sorry could not append history from file {}", e);
-                    }
-                }
-            }
-            _ => {}
-        }
-    }
-}
-
-fn append_history_on_exit (readline: &mut Editor<MyHelper, FileHistory> , last_entry: &mut usize) {
-    if let Some(location) = env::var_os("HISTFILE") {
-        let path = PathBuf::from(location);
-
-        let file_result = OpenOptions::new().read(true).write(true).append(true).open(path);
-        match file_result {
-            Ok(mut file) => {
-                for command in readline.history().iter().skip(*last_entry) {
-                    let _ = writeln!(file, "{}", command);
-                }
-
-                *last_entry = readline.history().len();
-            }
-            Err(_) => {
-                println!("Sorry could not save ur cmd history")
-            }
-        }
-    }
-}
\ No newline at end of file
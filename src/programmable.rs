@@ -0,0 +1,356 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use rustyline::completion::Pair;
+
+use crate::command_cache::command_names;
+use crate::completion::{matching_paths, word_candidate, PathFilter, WordContext};
+use crate::handler::{builtin_output_handler, set_last_status};
+
+// what `complete` registered for a command, also the actions `compgen` runs once
+#[derive(Clone, Default)]
+struct CompletionSpec {
+    words: Option<String>,
+    // -F or -C and the program to run, shelly has no shell functions so both name a command
+    program: Option<(char, String)>,
+    files: bool,
+    directories: bool,
+    commands: bool,
+    filenames: bool,
+    nospace: bool,
+    default: bool,
+    dirnames: bool,
+}
+
+static COMPLETION_SPECS: Mutex<Vec<(String, CompletionSpec)>> = Mutex::new(Vec::new());
+
+// the command line around the word being completed, handed to -F and -C programs
+struct CompletionLine<'a> {
+    line: &'a str,
+    point: usize,
+    words: Vec<String>,
+    current: usize,
+}
+
+// flags shared by `complete` and `compgen`, the rest are returned as operands
+fn parse_spec(args: &[String], command: &str) -> Result<(CompletionSpec, Vec<char>, Vec<String>), String> {
+    let mut spec = CompletionSpec::default();
+    let mut extra_flags: Vec<char> = Vec::new();
+    let mut operands: Vec<String> = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            operands.extend(args.by_ref().cloned());
+            break;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            operands.push(arg.clone());
+            continue;
+        };
+        for flag in flags.chars() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("{}: -{}: option requires an argument", command, flag))
+            };
+            match flag {
+                'W' => spec.words = Some(value()?),
+                'F' | 'C' => spec.program = Some((flag, value()?)),
+                'f' => spec.files = true,
+                'd' => spec.directories = true,
+                'c' => spec.commands = true,
+                'A' => match value()?.as_str() {
+                    "file" => spec.files = true,
+                    "directory" => spec.directories = true,
+                    "command" => spec.commands = true,
+                    action => return Err(format!("{}: {}: invalid action name", command, action)),
+                },
+                'o' => match value()?.as_str() {
+                    "filenames" => spec.filenames = true,
+                    "nospace" => spec.nospace = true,
+                    "default" => spec.default = true,
+                    "dirnames" => spec.dirnames = true,
+                    option => return Err(format!("{}: {}: invalid option name", command, option)),
+                },
+                'p' | 'r' => extra_flags.push(flag),
+                _ => return Err(format!("{}: -{}: invalid option", command, flag)),
+            }
+        }
+    }
+    Ok((spec, extra_flags, operands))
+}
+
+fn single_quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+// a spec in the form `complete` reads back, like `complete -p` in bash
+fn describe_spec(name: &str, spec: &CompletionSpec) -> String {
+    let mut parts = vec!["complete".to_string()];
+    for (on, option) in [
+        (spec.filenames, "filenames"),
+        (spec.nospace, "nospace"),
+        (spec.default, "default"),
+        (spec.dirnames, "dirnames"),
+    ] {
+        if on {
+            parts.push(format!("-o {}", option));
+        }
+    }
+    for (on, flag) in [(spec.files, "-f"), (spec.directories, "-d"), (spec.commands, "-c")] {
+        if on {
+            parts.push(flag.to_string());
+        }
+    }
+    if let Some(words) = &spec.words {
+        parts.push(format!("-W {}", single_quoted(words)));
+    }
+    if let Some((flag, program)) = &spec.program {
+        parts.push(format!("-{} {}", flag, program));
+    }
+    // a name that looks like an option would not read back
+    if name.starts_with('-') {
+        parts.push("--".to_string());
+    }
+    parts.push(name.to_string());
+    parts.join(" ")
+}
+
+// runs a -F/-C program as `program command word previous` with the bash COMP_ variables,
+// COMP_WORDS joined with spaces since the environment has no arrays. Every output line is
+// a candidate
+fn run_program(program: &str, completing: &CompletionLine) -> Vec<String> {
+    let word = completing.words.get(completing.current).map_or("", String::as_str);
+    let previous = completing
+        .current
+        .checked_sub(1)
+        .and_then(|i| completing.words.get(i))
+        .map_or("", String::as_str);
+    let command = completing.words.first().map_or("", String::as_str);
+
+    let output = Command::new(program)
+        .args([command, word, previous])
+        .env("COMP_WORDS", completing.words.join(" "))
+        .env("COMP_CWORD", completing.current.to_string())
+        .env("COMP_LINE", completing.line)
+        .env("COMP_POINT", completing.point.to_string())
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// candidates for `word` and whether each is a directory, in the order the actions run
+fn generate(spec: &CompletionSpec, word: &str, completing: &CompletionLine) -> Vec<(String, bool)> {
+    let mut results: Vec<(String, bool)> = Vec::new();
+    if spec.files || spec.directories {
+        let filter = if spec.files { PathFilter::All } else { PathFilter::Directories };
+        results.extend(matching_paths(word, filter).into_iter().map(|found| (found.path, found.is_dir)));
+    }
+    if spec.commands {
+        results.extend(command_names().into_iter().filter(|name| name.starts_with(word)).map(|name| (name, false)));
+    }
+    if let Some(words) = &spec.words {
+        results.extend(
+            words
+                .split_whitespace()
+                .filter(|candidate| candidate.starts_with(word))
+                .map(|candidate| (candidate.to_string(), false)),
+        );
+    }
+    if let Some((_, program)) = &spec.program {
+        // like COMPREPLY, whatever the program prints is used as it is
+        results.extend(run_program(program, completing).into_iter().map(|candidate| {
+            let is_dir = spec.filenames && Path::new(&candidate).is_dir();
+            (candidate, is_dir)
+        }));
+    }
+    if results.is_empty() && spec.dirnames {
+        results.extend(matching_paths(word, PathFilter::Directories).into_iter().map(|found| (found.path, true)));
+    }
+    results
+}
+
+// candidates from the spec registered for the command being completed, None if there is
+// none. A spec that finds nothing completes nothing, unless `-o default` asks for filenames
+pub fn spec_candidates(context: &WordContext, line: &str, pos: usize) -> Option<Vec<Pair>> {
+    let command = context.words_before.first()?;
+    let name = Path::new(command).file_name()?.to_str()?;
+    let spec = COMPLETION_SPECS
+        .lock()
+        .ok()?
+        .iter()
+        .find(|(registered, _)| registered == name)
+        .map(|(_, spec)| spec.clone())?;
+
+    let mut words = context.words_before.clone();
+    words.push(context.word.clone());
+    let completing = CompletionLine {
+        line,
+        point: pos,
+        current: words.len() - 1,
+        words,
+    };
+    let mut candidates: Vec<Pair> = generate(&spec, &context.word, &completing)
+        .iter()
+        .map(|(text, is_dir)| {
+            // -o filenames shows just the last part of a path, like file completion does
+            let display = if spec.filenames || spec.files || spec.directories {
+                text.trim_end_matches('/').rsplit('/').next().unwrap_or(text)
+            } else {
+                text.as_str()
+            };
+            word_candidate(text, display, *is_dir, context.quote, !spec.nospace)
+        })
+        .collect();
+    candidates.dedup_by(|a, b| a.replacement == b.replacement);
+    if candidates.is_empty() && spec.default { None } else { Some(candidates) }
+}
+
+fn complete_output(args: &[String], command: &str) -> Result<String, String> {
+    let command = command.trim();
+    let (spec, extra_flags, names) = parse_spec(args, command)?;
+    let Ok(mut specs) = COMPLETION_SPECS.lock() else {
+        return Err(format!("{}: completion specs unavailable", command));
+    };
+
+    if extra_flags.contains(&'r') {
+        if names.is_empty() {
+            specs.clear();
+        }
+        for name in &names {
+            if !specs.iter().any(|(registered, _)| registered == name) {
+                return Err(format!("{}: {}: no completion specification", command, name));
+            }
+            specs.retain(|(registered, _)| registered != name);
+        }
+        return Ok(String::new());
+    }
+
+    if extra_flags.contains(&'p') || names.is_empty() {
+        let mut lines: Vec<String> = Vec::new();
+        for (registered, spec) in specs.iter() {
+            if names.is_empty() || names.contains(registered) {
+                lines.push(describe_spec(registered, spec));
+            }
+        }
+        if let Some(missing) = names.iter().find(|name| !specs.iter().any(|(registered, _)| registered == *name)) {
+            return Err(format!("{}: {}: no completion specification", command, missing));
+        }
+        return Ok(lines.join("\n"));
+    }
+
+    for name in names {
+        specs.retain(|(registered, _)| *registered != name);
+        specs.push((name, spec.clone()));
+    }
+    Ok(String::new())
+}
+
+pub fn complete_handler(args: &[String], command: &str, redirect: bool, redirects: Vec<(String, String)>) {
+    builtin_output_handler(complete_output(args, command), redirect, redirects);
+}
+
+// `compgen [actions] [word]` prints what those actions would offer for `word`
+pub fn compgen_output(args: &[String], command: &str) -> Result<String, String> {
+    let command = command.trim();
+    let (spec, extra_flags, operands) = parse_spec(args, command)?;
+    if let Some(flag) = extra_flags.first() {
+        return Err(format!("{}: -{}: invalid option", command, flag));
+    }
+    if operands.len() > 1 {
+        return Err(format!("{}: too many arguments", command));
+    }
+    let word = operands.first().map_or("", String::as_str);
+    let words = vec![String::new(), word.to_string()];
+    let completing = CompletionLine {
+        line: word,
+        point: word.len(),
+        words,
+        current: 1,
+    };
+
+    let results = generate(&spec, word, &completing);
+    let lines: Vec<String> = results.into_iter().map(|(text, _)| text).collect();
+    Ok(lines.join("\n"))
+}
+
+pub fn compgen_handler(args: &[String], command: &str, redirect: bool, redirects: Vec<(String, String)>) {
+    let result = compgen_output(args, command);
+    let found_nothing = matches!(&result, Ok(lines) if lines.is_empty());
+    builtin_output_handler(result, redirect, redirects);
+    // bash reports an empty result through the exit status only
+    if found_nothing {
+        set_last_status(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_parser::input_parser;
+
+    fn args(line: &str) -> Vec<String> {
+        input_parser(line).unwrap().1.remove(0)
+    }
+
+    fn described(line: &str) -> Result<String, String> {
+        let (spec, _, names) = parse_spec(&args(line), "complete")?;
+        Ok(describe_spec(&names.join(" "), &spec))
+    }
+
+    #[test]
+    fn options_are_parsed_and_described() {
+        let cases = [
+            ("-W 'start stop' svc", "complete -W 'start stop' svc"),
+            ("-fd x", "complete -f -d x"),
+            ("-A directory -A command x", "complete -d -c x"),
+            ("-o nospace -o default -F prog x", "complete -o nospace -o default -F prog x"),
+            ("-o filenames -C /bin/prog -o dirnames x", "complete -o filenames -o dirnames -C /bin/prog x"),
+            ("-W \"it's\" x", "complete -W 'it'\\''s' x"),
+            ("-f -- -x", "complete -f -- -x"),
+        ];
+        for (line, expected) in cases {
+            let description = described(line).unwrap();
+            assert_eq!(description, expected);
+            // `complete -p` prints what reads back as the same spec
+            assert_eq!(described(description.strip_prefix("complete ").unwrap()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn bad_options() {
+        let cases = [
+            ("-W", "complete: -W: option requires an argument"),
+            ("-o", "complete: -o: option requires an argument"),
+            ("-z x", "complete: -z: invalid option"),
+            ("-A user x", "complete: user: invalid action name"),
+            ("-o bashdefault x", "complete: bashdefault: invalid option name"),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(described(line).unwrap_err(), expected);
+        }
+        let (_, extra_flags, names) = parse_spec(&args("-pr git"), "complete").unwrap();
+        assert_eq!((extra_flags, names), (vec!['p', 'r'], vec!["git".to_string()]));
+    }
+
+    #[test]
+    fn compgen_filters_words() {
+        let compgen = |line: &str| compgen_output(&args(line), "compgen");
+        assert_eq!(compgen("-W 'start stop status' st").unwrap(), "start\nstop\nstatus");
+        assert_eq!(compgen("-W 'start stop status' sta").unwrap(), "start\nstatus");
+        assert_eq!(compgen("-W 'start stop' x").unwrap(), "");
+        assert_eq!(compgen("-W a -- -a").unwrap(), "");
+        assert_eq!(compgen("-p").unwrap_err(), "compgen: -p: invalid option");
+        assert_eq!(compgen("-W a b c").unwrap_err(), "compgen: too many arguments");
+    }
+}
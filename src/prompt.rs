@@ -0,0 +1,403 @@
+use std::env;
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::git_prompt::git_segment;
+use crate::handler::last_status;
+use crate::highlight::highlight_line;
+use crate::history_picker::terminal_size;
+use crate::history_store::{current_directory, host_name};
+use crate::time_format::{format_local_time, now_epoch};
+
+// what bash shows when PS1 is unset would be `\s-\v\$ `, shelly shows where you are
+const DEFAULT_PS1: &str = "\\w \\$ ";
+const DEFAULT_PS2: &str = "> ";
+const DEFAULT_PS4: &str = "+ ";
+// what `shopt -s transientprompt` leaves of a prompt once its line is accepted
+const DEFAULT_TRANSIENT_PROMPT: &str = "\\$ ";
+
+// how long the last command line took, for `\c`
+static LAST_DURATION_MS: AtomicU64 = AtomicU64::new(0);
+
+// numbers the prompt escapes `\!` and `\#` show
+pub struct PromptCounts {
+    pub history_number: usize,
+    pub command_number: usize,
+}
+
+// an expanded prompt: `plain` is what rustyline measures, without the `\[...\]` parts,
+// `styled` is what gets drawn
+#[derive(Default)]
+pub struct Prompt {
+    pub plain: String,
+    pub styled: String,
+}
+
+pub fn set_last_duration(duration: Duration) {
+    LAST_DURATION_MS.store(duration.as_millis() as u64, Ordering::Relaxed);
+}
+
+// `35ms`, `2.4s`, `3m07s`, `1h05m`
+fn format_duration(millis: u64) -> String {
+    let seconds = millis / 1000;
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else if seconds < 60 {
+        format!("{:.1}s", millis as f64 / 1000.0)
+    } else if seconds < 3600 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}h{:02}m", seconds / 3600, seconds / 60 % 60)
+    }
+}
+
+fn user_name() -> String {
+    if let Ok(user) = env::var("USER").or_else(|_| env::var("LOGNAME"))
+        && !user.is_empty()
+    {
+        return user;
+    }
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    // SAFETY: all pointers point into buffers that outlive the call
+    let status = unsafe { libc::getpwuid_r(libc::geteuid(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if status != 0 || result.is_null() || entry.pw_name.is_null() {
+        return String::new();
+    }
+    // SAFETY: pw_name is a nul terminated string inside `buffer`
+    unsafe { CStr::from_ptr(entry.pw_name) }.to_string_lossy().into_owned()
+}
+
+// the working directory with $HOME shortened to `~`
+fn short_directory() -> String {
+    let cwd = current_directory();
+    match env::var("HOME") {
+        Ok(home) if !home.is_empty() && home != "/" => match cwd.strip_prefix(&home) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{}", rest),
+            _ => cwd,
+        },
+        _ => cwd,
+    }
+}
+
+fn terminal_name() -> String {
+    // SAFETY: ttyname returns a static buffer or null, it is copied right away
+    let name = unsafe { libc::ttyname(libc::STDIN_FILENO) };
+    if name.is_null() {
+        return "tty".to_string();
+    }
+    // SAFETY: checked for null above, ttyname gives a nul terminated string
+    let path = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+    path.rsplit('/').next().unwrap_or(&path).to_string()
+}
+
+// output of `command` run by sh with trailing newlines dropped, like `$(...)`
+fn substitute(command: &str) -> String {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output();
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).trim_end_matches('\n').to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+// the text of a `$(...)` starting after the `$(`, and the byte length it took up
+fn substitution_body(rest: &str) -> Option<(&str, usize)> {
+    let mut depth = 1;
+    let mut quote: Option<char> = None;
+    for (i, c) in rest.char_indices() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&rest[..i], i + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// expands the bash prompt escapes, `$NAME`, `$(command)` and `` `command` `` in `template`
+pub fn expand_prompt(template: &str, counts: &PromptCounts) -> Prompt {
+    let mut plain = String::new();
+    let mut styled = String::new();
+    let mut hidden = false;
+    let mut rest = template;
+
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        let text: String = match c {
+            '\\' => {
+                let Some(escape) = rest.chars().next() else {
+                    plain.push('\\');
+                    styled.push('\\');
+                    break;
+                };
+                rest = &rest[escape.len_utf8()..];
+                let now = now_epoch();
+                match escape {
+                    'a' => "\x07".to_string(),
+                    'e' => "\x1b".to_string(),
+                    'n' => "\n".to_string(),
+                    'r' => "\r".to_string(),
+                    '\\' => "\\".to_string(),
+                    'd' => format_local_time("%a %b %d", now),
+                    'D' if rest.starts_with('{') => {
+                        let close = rest.find('}').unwrap_or(rest.len());
+                        let format = match &rest[1..close] {
+                            "" => "%X",
+                            format => format,
+                        };
+                        let formatted = format_local_time(format, now);
+                        rest = rest.get(close + 1..).unwrap_or("");
+                        formatted
+                    }
+                    't' => format_local_time("%H:%M:%S", now),
+                    'T' => format_local_time("%I:%M:%S", now),
+                    '@' => format_local_time("%I:%M %p", now),
+                    'A' => format_local_time("%H:%M", now),
+                    'u' => user_name(),
+                    'h' => host_name().split('.').next().unwrap_or("").to_string(),
+                    'H' => host_name().to_string(),
+                    'w' => short_directory(),
+                    'W' => {
+                        let directory = short_directory();
+                        match directory.as_str() {
+                            "/" | "~" => directory,
+                            _ => directory.rsplit('/').next().unwrap_or("").to_string(),
+                        }
+                    }
+                    's' => env!("CARGO_PKG_NAME").to_string(),
+                    'v' => env!("CARGO_PKG_VERSION").rsplitn(2, '.').last().unwrap_or("").to_string(),
+                    'V' => env!("CARGO_PKG_VERSION").to_string(),
+                    // no job control, so never any jobs
+                    'j' => "0".to_string(),
+                    'l' => terminal_name(),
+                    // not bash, the branch and state of the repository, see git_prompt
+                    'g' => git_segment(),
+                    // not bash either, how long the last command took
+                    'c' => format_duration(LAST_DURATION_MS.load(Ordering::Relaxed)),
+                    '!' => counts.history_number.to_string(),
+                    '#' => counts.command_number.to_string(),
+                    '?' => last_status().to_string(),
+                    // SAFETY: geteuid cannot fail
+                    '$' => if unsafe { libc::geteuid() } == 0 { "#" } else { "$" }.to_string(),
+                    '[' => {
+                        hidden = true;
+                        continue;
+                    }
+                    ']' => {
+                        hidden = false;
+                        continue;
+                    }
+                    '0'..='7' => {
+                        // up to three octal digits
+                        let digits: String = std::iter::once(escape)
+                            .chain(rest.chars().take_while(|d| d.is_digit(8)).take(2))
+                            .collect();
+                        rest = &rest[digits.len() - 1..];
+                        u8::from_str_radix(&digits, 8).map_or(String::new(), |byte| char::from(byte).to_string())
+                    }
+                    other => format!("\\{}", other),
+                }
+            }
+            '$' if rest.starts_with('(') => match substitution_body(&rest[1..]) {
+                Some((command, length)) => {
+                    rest = &rest[1 + length..];
+                    substitute(command)
+                }
+                None => "$".to_string(),
+            },
+            '`' => match rest.find('`') {
+                Some(close) => {
+                    let output = substitute(&rest[..close]);
+                    rest = &rest[close + 1..];
+                    output
+                }
+                None => "`".to_string(),
+            },
+            '$' => {
+                let braced = rest.strip_prefix('{').and_then(|inner| Some((inner, inner.find('}')?)));
+                let (name, length) = match braced {
+                    Some((inner, close)) => (&inner[..close], close + 2),
+                    // `$1` is a positional parameter, one digit long
+                    None if rest.starts_with(|c: char| c.is_ascii_digit()) => (&rest[..1], 1),
+                    None => {
+                        let length = rest
+                            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                            .unwrap_or(rest.len());
+                        (&rest[..length], length)
+                    }
+                };
+                if rest.starts_with('?') {
+                    rest = &rest[1..];
+                    last_status().to_string()
+                } else if name.is_empty() {
+                    "$".to_string()
+                } else {
+                    rest = &rest[length..];
+                    env::var(name).unwrap_or_default()
+                }
+            }
+            c => c.to_string(),
+        };
+        styled.push_str(&text);
+        if !hidden {
+            plain.push_str(&text);
+        }
+    }
+    Prompt { plain, styled }
+}
+
+fn prompt_variable(name: &str, default: &str) -> String {
+    env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+pub fn primary_prompt(counts: &PromptCounts) -> Prompt {
+    expand_prompt(&prompt_variable("PS1", DEFAULT_PS1), counts)
+}
+
+pub fn continuation_prompt(counts: &PromptCounts) -> Prompt {
+    expand_prompt(&prompt_variable("PS2", DEFAULT_PS2), counts)
+}
+
+// shown before each command `shopt -s xtrace` traces
+pub fn trace_prompt(counts: &PromptCounts) -> Prompt {
+    expand_prompt(&prompt_variable("PS4", DEFAULT_PS4), counts)
+}
+
+// drawn at the right edge of the input line, like zsh's RPROMPT
+pub fn right_prompt(counts: &PromptCounts) -> Prompt {
+    expand_prompt(&prompt_variable("RPROMPT", ""), counts)
+}
+
+pub fn transient_prompt(counts: &PromptCounts) -> Prompt {
+    expand_prompt(&prompt_variable("TRANSIENT_PROMPT", DEFAULT_TRANSIENT_PROMPT), counts)
+}
+
+// columns `text` takes up: wide characters like CJK and most emoji take two, combining
+// marks and control characters none
+pub fn text_width(text: &str) -> usize {
+    text.chars().filter_map(UnicodeWidthChar::width).sum()
+}
+
+// draws `right` at the right edge of the current row and puts the cursor back where it
+// was, empty when it would not fit after the `used` columns. Like zsh, the last column
+// stays free since writing there makes some terminals wrap
+pub fn right_prompt_sequence(right: &Prompt, used: usize) -> String {
+    if right.plain.is_empty() || right.plain.contains('\n') {
+        return String::new();
+    }
+    let (columns, _) = terminal_size();
+    let width = text_width(&right.plain);
+    if used + 1 + width + 1 > columns {
+        return String::new();
+    }
+    format!("\x1b7\x1b[{}G{}\x1b8", columns - width, right.styled)
+}
+
+// draws the line just accepted again after `transient` in place of the prompt it was typed
+// at, `shown` is that prompt as rustyline measured it. The cursor is on the row after the
+// line, so this goes up over the rows the two took and clears them
+pub fn collapse_prompt(shown: &str, line: &str, transient: &Prompt) {
+    // SAFETY: isatty only looks at the descriptor
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 || unsafe { libc::isatty(libc::STDOUT_FILENO) } != 1 {
+        return;
+    }
+    let (columns, rows) = terminal_size();
+    let used: usize = format!("{}{}", shown, line)
+        .split('\n')
+        .map(|row| text_width(row).saturating_sub(1) / columns + 1)
+        .sum();
+    // rows that scrolled off the screen cannot be reached any more
+    if used >= rows {
+        return;
+    }
+    let painted = highlight_line(line, 0, false).unwrap_or_else(|| line.to_string());
+    let mut stdout = io::stdout();
+    let _ = write!(stdout, "\x1b[{}A\r\x1b[J{}{}\n", used, transient.styled, painted);
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(template: &str) -> Prompt {
+        let counts = PromptCounts {
+            history_number: 12,
+            command_number: 3,
+        };
+        expand_prompt(template, &counts)
+    }
+
+    #[test]
+    fn fixed_escapes() {
+        // SAFETY: geteuid cannot fail
+        let dollar = if unsafe { libc::geteuid() } == 0 { "#" } else { "$" };
+        let cases = [
+            ("\\!:\\#", "12:3".to_string()),
+            ("\\a\\e\\r\\n\\\\", "\x07\x1b\r\n\\".to_string()),
+            ("\\033[1m\\101\\0101", "\x1b[1mA\x081".to_string()),
+            ("\\j \\s \\V", format!("0 {} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))),
+            ("\\$ ", format!("{} ", dollar)),
+            ("\\q and \\", "\\q and \\".to_string()),
+            ("100% $ $1x", "100% $ x".to_string()),
+            ("${SHELLY_UNSET_IN_TESTS}|$SHELLY_UNSET_IN_TESTS-", "|-".to_string()),
+        ];
+        for (template, expected) in cases {
+            assert_eq!(expand(template).plain, expected, "{:?}", template);
+        }
+        assert_eq!(expand("\\D{%Y}").plain.len(), 4);
+        assert!(expand("\\?").plain.parse::<i32>().is_ok());
+    }
+
+    #[test]
+    fn hidden_parts_are_drawn_but_not_measured() {
+        let prompt = expand("\\[\\e[32m\\]ok\\[\\e[0m\\] ");
+        assert_eq!(prompt.plain, "ok ");
+        assert_eq!(prompt.styled, "\x1b[32mok\x1b[0m ");
+    }
+
+    #[test]
+    fn command_substitution() {
+        assert_eq!(expand("[$(printf '%s' 'a)b')]").plain, "[a)b]");
+        assert_eq!(expand("[$(echo $(echo in))]").plain, "[in]");
+        assert_eq!(expand("[`printf x`]").plain, "[x]");
+        // left open it is plain text
+        assert_eq!(expand("$(echo").plain, "$(echo");
+        assert_eq!(expand("`echo").plain, "`echo");
+    }
+
+    #[test]
+    fn durations() {
+        let cases = [(35, "35ms"), (2400, "2.4s"), (187_000, "3m07s"), (3_900_000, "1h05m")];
+        for (millis, expected) in cases {
+            assert_eq!(format_duration(millis), expected);
+        }
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(text_width("abc"), 3);
+        assert_eq!(text_width("日本"), 4);
+        assert_eq!(text_width("e\u{301}"), 1);
+        assert_eq!(text_width("\x1b"), 0);
+    }
+}
@@ -0,0 +1,435 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rustyline::completion::Pair;
+
+use crate::completion::{word_candidate, WordContext};
+use crate::git::{find_git_dir, ref_names, remote_names};
+
+// a native completer for the arguments of some commands, asked after `complete` specs
+// and before filenames. `words` are the finished words, the command first
+pub trait CompletionProvider: Sync {
+    fn commands(&self) -> &[&str];
+    fn candidates(&self, words: &[String], current: &str) -> Vec<String>;
+}
+
+struct GitProvider;
+struct CargoProvider;
+struct MakeProvider;
+struct SshProvider;
+
+static PROVIDERS: [&dyn CompletionProvider; 4] = [&GitProvider, &CargoProvider, &MakeProvider, &SshProvider];
+
+const GIT_SUBCOMMANDS: [&str; 33] = [
+    "add", "am", "bisect", "blame", "branch", "checkout", "cherry-pick", "clean", "clone", "commit",
+    "config", "describe", "diff", "fetch", "grep", "init", "log", "merge", "mv", "pull", "push",
+    "rebase", "reflog", "remote", "reset", "restore", "revert", "rm", "show", "stash", "status",
+    "switch", "tag",
+];
+
+const CARGO_SUBCOMMANDS: [&str; 24] = [
+    "add", "bench", "build", "check", "clean", "clippy", "doc", "fetch", "fix", "fmt", "init",
+    "install", "metadata", "new", "package", "publish", "remove", "run", "search", "test", "tree",
+    "uninstall", "update", "vendor",
+];
+
+// the first word after the command that is not an option
+fn subcommand(words: &[String]) -> Option<&str> {
+    words.iter().skip(1).find(|word| !word.starts_with('-')).map(String::as_str)
+}
+
+fn current_dir() -> PathBuf {
+    env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+}
+
+impl CompletionProvider for GitProvider {
+    fn commands(&self) -> &[&str] {
+        &["git"]
+    }
+
+    fn candidates(&self, words: &[String], _current: &str) -> Vec<String> {
+        let Some(subcommand) = subcommand(words) else {
+            return GIT_SUBCOMMANDS.iter().map(|name| name.to_string()).collect();
+        };
+        let Some(git_dir) = find_git_dir(&current_dir()) else {
+            return Vec::new();
+        };
+        let branches = ref_names(&git_dir, "refs/heads/");
+        let remote_branches = ref_names(&git_dir, "refs/remotes/");
+        let tags = ref_names(&git_dir, "refs/tags/");
+        let arguments = words.iter().skip(1).filter(|word| !word.starts_with('-')).count();
+
+        match subcommand {
+            "checkout" | "switch" => {
+                // remote branches can be checked out by their short name
+                let mut names = branches;
+                names.extend(remote_branches.iter().filter_map(|name| Some(name.split_once('/')?.1.to_string())));
+                names.retain(|name| name != "HEAD");
+                names
+            }
+            "branch" | "merge" | "rebase" | "log" | "diff" | "show" | "reset" | "cherry-pick" | "revert" => {
+                let mut names = branches;
+                names.extend(remote_branches);
+                names.extend(tags);
+                names
+            }
+            "tag" => tags,
+            "push" | "pull" | "fetch" if arguments == 1 => remote_names(&git_dir),
+            "push" | "pull" | "fetch" => branches,
+            "remote" if arguments == 1 => ["add", "remove", "rename", "set-url", "show", "prune"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            "remote" => remote_names(&git_dir),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// package, bin, example, test and bench names of the Cargo.toml closest to the cwd
+#[derive(Default)]
+struct CargoTargets {
+    packages: Vec<String>,
+    bins: Vec<String>,
+    examples: Vec<String>,
+    tests: Vec<String>,
+    benches: Vec<String>,
+}
+
+// `name = "value"` from a manifest line
+fn manifest_string(line: &str, key: &str) -> Option<String> {
+    let (name, value) = line.split_once('=')?;
+    if name.trim() != key {
+        return None;
+    }
+    Some(value.trim().trim_matches('"').to_string())
+}
+
+// the `.rs` files and directories of `dir`, which cargo turns into targets by name
+fn target_files(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if entry.path().is_dir() {
+                return Some(name);
+            }
+            name.strip_suffix(".rs").map(String::from)
+        })
+        .collect()
+}
+
+fn read_manifest(root: &Path, targets: &mut CargoTargets) {
+    let Ok(manifest) = fs::read_to_string(root.join("Cargo.toml")) else {
+        return;
+    };
+    let mut section = String::new();
+    let mut members: Vec<String> = Vec::new();
+    let mut in_members = false;
+    for line in manifest.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            section = line.trim_matches(|c| c == '[' || c == ']').to_string();
+            in_members = false;
+            continue;
+        }
+        if let Some(name) = manifest_string(line, "name") {
+            match section.as_str() {
+                "package" => targets.packages.push(name),
+                "bin" => targets.bins.push(name),
+                "example" => targets.examples.push(name),
+                "test" => targets.tests.push(name),
+                "bench" => targets.benches.push(name),
+                _ => {}
+            }
+        }
+        // workspace members, on one line or spread over several
+        if section == "workspace" && (line.starts_with("members") || in_members) {
+            let list = line.split_once('[').map_or(line, |(_, rest)| rest);
+            in_members = !list.contains(']');
+            let list = list.split(']').next().unwrap_or(list);
+            members.extend(
+                list.split(',')
+                    .map(|member| member.trim().trim_matches('"'))
+                    .filter(|member| !member.is_empty() && !member.contains('*'))
+                    .map(String::from),
+            );
+        }
+    }
+
+    if root.join("src/main.rs").is_file()
+        && let Some(package) = targets.packages.first()
+    {
+        targets.bins.push(package.clone());
+    }
+    targets.bins.extend(target_files(&root.join("src/bin")));
+    targets.examples.extend(target_files(&root.join("examples")));
+    targets.tests.extend(target_files(&root.join("tests")));
+    targets.benches.extend(target_files(&root.join("benches")));
+    for member in members {
+        read_manifest(&root.join(member), targets);
+    }
+}
+
+fn cargo_targets() -> CargoTargets {
+    let mut targets = CargoTargets::default();
+    let cwd = current_dir();
+    if let Some(root) = cwd.ancestors().find(|dir| dir.join("Cargo.toml").is_file()) {
+        read_manifest(root, &mut targets);
+    }
+    targets
+}
+
+impl CompletionProvider for CargoProvider {
+    fn commands(&self) -> &[&str] {
+        &["cargo"]
+    }
+
+    fn candidates(&self, words: &[String], _current: &str) -> Vec<String> {
+        if subcommand(words).is_none() {
+            return CARGO_SUBCOMMANDS.iter().map(|name| name.to_string()).collect();
+        }
+        let targets = cargo_targets();
+        match words.last().map(String::as_str) {
+            Some("--bin") => targets.bins,
+            Some("--example") => targets.examples,
+            Some("--test") => targets.tests,
+            Some("--bench") => targets.benches,
+            Some("-p" | "--package") => targets.packages,
+            _ => Vec::new(),
+        }
+    }
+}
+
+// rule targets of the makefile, leaving out special ones like `.PHONY`, pattern rules
+// and variable assignments
+fn make_targets(makefile: &Path) -> Vec<String> {
+    let Ok(contents) = fs::read_to_string(makefile) else {
+        return Vec::new();
+    };
+    let mut targets: Vec<String> = Vec::new();
+    for line in contents.lines() {
+        if line.starts_with(['\t', ' ', '#', '.']) {
+            continue;
+        }
+        let Some((names, rest)) = line.split_once(':') else {
+            continue;
+        };
+        // `:=`, `::=` and `:::=` assign, `::` alone is a double colon rule
+        if rest.trim_start_matches(':').starts_with('=') || names.contains('=') {
+            continue;
+        }
+        targets.extend(
+            names
+                .split_whitespace()
+                .filter(|name| !name.contains(['%', '$']))
+                .map(String::from),
+        );
+    }
+    targets
+}
+
+impl CompletionProvider for MakeProvider {
+    fn commands(&self) -> &[&str] {
+        &["make", "gmake"]
+    }
+
+    fn candidates(&self, words: &[String], _current: &str) -> Vec<String> {
+        // a file or directory argument is what -f and -C want
+        if matches!(words.last().map(String::as_str), Some("-f" | "-C")) {
+            return Vec::new();
+        }
+        let dir = words
+            .windows(2)
+            .find(|pair| pair[0] == "-C")
+            .map_or_else(current_dir, |pair| PathBuf::from(&pair[1]));
+        let makefile = words
+            .windows(2)
+            .find(|pair| pair[0] == "-f")
+            .map(|pair| dir.join(&pair[1]))
+            .or_else(|| {
+                ["GNUmakefile", "makefile", "Makefile"]
+                    .iter()
+                    .map(|name| dir.join(name))
+                    .find(|path| path.is_file())
+            });
+        makefile.map_or_else(Vec::new, |makefile| make_targets(&makefile))
+    }
+}
+
+// `Host` names from ~/.ssh/config without wildcards, plus every host in known_hosts
+// that is not hashed
+fn ssh_hosts(home: &Path) -> Vec<String> {
+    let mut hosts: Vec<String> = Vec::new();
+
+    if let Ok(config) = fs::read_to_string(home.join(".ssh/config")) {
+        for line in config.lines() {
+            let mut fields = line.split_whitespace();
+            if fields.next().is_some_and(|keyword| keyword.eq_ignore_ascii_case("host")) {
+                hosts.extend(fields.filter(|host| !host.contains(['*', '?', '!'])).map(String::from));
+            }
+        }
+    }
+
+    if let Ok(known_hosts) = fs::read_to_string(home.join(".ssh/known_hosts")) {
+        for line in known_hosts.lines() {
+            let Some(field) = line.split_whitespace().next() else {
+                continue;
+            };
+            if field.starts_with(['#', '|', '@']) {
+                continue;
+            }
+            for host in field.split(',') {
+                // `[host]:port` for non standard ports
+                let host = host.strip_prefix('[').and_then(|host| host.split_once(']')).map_or(host, |(host, _)| host);
+                hosts.push(host.to_string());
+            }
+        }
+    }
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+impl CompletionProvider for SshProvider {
+    fn commands(&self) -> &[&str] {
+        &["ssh", "sftp", "ssh-copy-id"]
+    }
+
+    fn candidates(&self, words: &[String], current: &str) -> Vec<String> {
+        // options that take a value, so the next word is not the host
+        const WITH_VALUE: [&str; 12] = ["-b", "-c", "-D", "-E", "-F", "-i", "-J", "-l", "-L", "-o", "-p", "-R"];
+        if words.last().is_some_and(|word| WITH_VALUE.contains(&word.as_str())) {
+            return Vec::new();
+        }
+        let Some(home) = env::var_os("HOME") else {
+            return Vec::new();
+        };
+        let hosts = ssh_hosts(Path::new(&home));
+        // `user@` keeps its user part
+        match current.split_once('@') {
+            Some((user, _)) => hosts.into_iter().map(|host| format!("{}@{}", user, host)).collect(),
+            None => hosts,
+        }
+    }
+}
+
+// candidates from the provider for the command being completed, None if there is none
+// or it has nothing to offer so filenames are used instead
+pub fn provider_candidates(context: &WordContext) -> Option<Vec<Pair>> {
+    let command = context.words_before.first()?;
+    let name = Path::new(command).file_name()?.to_str()?;
+    let provider = PROVIDERS.iter().find(|provider| provider.commands().contains(&name))?;
+
+    let mut names = provider.candidates(&context.words_before, &context.word);
+    names.retain(|candidate| candidate.starts_with(&context.word));
+    names.sort();
+    names.dedup();
+    let candidates: Vec<Pair> = names
+        .iter()
+        .map(|candidate| word_candidate(candidate, candidate, false, context.quote, true))
+        .collect();
+    if candidates.is_empty() { None } else { Some(candidates) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("shelly-providers-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn makefile_targets() {
+        let dir = temp_dir("make");
+        let cases = [
+            ("all: build\n\tcc -o app main.c\n", vec!["all"]),
+            ("build test: deps\n", vec!["build", "test"]),
+            (".PHONY: all\n# clean: old\n  indented: no\n", vec![]),
+            ("CC := gcc\nFLAGS = -O2 -Da:b\nX ::= y\n", vec![]),
+            ("%.o: %.c\n$(OUT): x\nlib.a: x.o\n", vec!["lib.a"]),
+            ("install:: one\n", vec!["install"]),
+        ];
+        let results: Vec<_> = cases
+            .iter()
+            .map(|(contents, _)| {
+                fs::write(dir.join("Makefile"), contents).unwrap();
+                make_targets(&dir.join("Makefile"))
+            })
+            .collect();
+        let _ = fs::remove_dir_all(&dir);
+        for ((contents, expected), result) in cases.iter().zip(results) {
+            assert_eq!(result, *expected, "{:?}", contents);
+        }
+        assert!(make_targets(&dir.join("Makefile")).is_empty());
+    }
+
+    #[test]
+    fn cargo_manifest_targets() {
+        let root = temp_dir("cargo");
+        let files = [
+            ("Cargo.toml", "[workspace]\nmembers = [\n  \"app\",\n  \"crates/*\",\n]\n"),
+            (
+                "app/Cargo.toml",
+                "[package]\nname = \"app\"\n\n[dependencies]\nname = \"not-a-target\"\n\n[[bin]]\nname = \"tool\"\n[[example]]\nname=\"demo\"\n",
+            ),
+            ("app/src/main.rs", ""),
+            ("app/src/bin/extra.rs", ""),
+            ("app/src/bin/multi/main.rs", ""),
+            ("app/tests/cli.rs", ""),
+            ("app/benches/speed.rs", ""),
+            ("app/examples/README.md", ""),
+        ];
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let mut targets = CargoTargets::default();
+        read_manifest(&root, &mut targets);
+        let _ = fs::remove_dir_all(&root);
+
+        let sorted = |names: &[String]| {
+            let mut names = names.to_vec();
+            names.sort();
+            names
+        };
+        let cases = [
+            (sorted(&targets.packages), vec!["app"]),
+            (sorted(&targets.bins), vec!["app", "extra", "multi", "tool"]),
+            (sorted(&targets.examples), vec!["demo"]),
+            (sorted(&targets.tests), vec!["cli"]),
+            (sorted(&targets.benches), vec!["speed"]),
+        ];
+        for (got, expected) in cases {
+            assert_eq!(got, expected);
+        }
+    }
+
+    #[test]
+    fn ssh_config_and_known_hosts() {
+        let home = temp_dir("ssh");
+        fs::create_dir_all(home.join(".ssh")).unwrap();
+        fs::write(
+            home.join(".ssh/config"),
+            "Host work box\n  HostName 10.0.0.1\nhost *.internal !bad gate?\nHOST   laptop\nMatch host other\n",
+        )
+        .unwrap();
+        fs::write(
+            home.join(".ssh/known_hosts"),
+            "github.com,140.82.0.1 ssh-ed25519 AAAA\n[git.example]:2222 ssh-rsa AAAA\n|1|hashed= ssh-rsa AAAA\n# comment\n@revoked bad ssh-rsa AAAA\n\nwork ssh-rsa AAAA\n",
+        )
+        .unwrap();
+        let hosts = ssh_hosts(&home);
+        let _ = fs::remove_dir_all(&home);
+        assert_eq!(hosts, ["140.82.0.1", "box", "git.example", "github.com", "laptop", "work"]);
+        assert!(ssh_hosts(&home).is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::Mutex;
+
+use crate::handler::builtin_output_handler;
+
+// every option `shopt` knows about, all of them start off
+pub const SHOPT_OPTIONS: [&str; 5] = ["autocd", "cdspell", "histshare", "transientprompt", "xtrace"];
+
+static ENABLED_OPTIONS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+pub fn shopt_enabled(name: &str) -> bool {
+    ENABLED_OPTIONS
+        .lock()
+        .map(|enabled| enabled.contains(&name))
+        .unwrap_or(false)
+}
+
+fn set_option(name: &'static str, on: bool) {
+    if let Ok(mut enabled) = ENABLED_OPTIONS.lock() {
+        enabled.retain(|option| *option != name);
+        if on {
+            enabled.push(name);
+        }
+    }
+}
+
+fn shopt_output(args: &[String], command: &str) -> Result<String, String> {
+    let mut set: Option<bool> = None;
+    let mut quiet = false;
+    let mut reusable = false;
+    let mut names: Vec<&'static str> = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "-s" => set = Some(true),
+            "-u" => set = Some(false),
+            "-q" => quiet = true,
+            "-p" => reusable = true,
+            _ if arg.starts_with('-') => {
+                return Err(format!("{}: {}: invalid option", command.trim(), arg));
+            }
+            _ => match SHOPT_OPTIONS.iter().find(|option| **option == arg.as_str()) {
+                Some(option) => names.push(option),
+                None => {
+                    return Err(format!("{}: {}: invalid shell option name", command.trim(), arg));
+                }
+            },
+        }
+    }
+
+    if let Some(on) = set {
+        for name in &names {
+            set_option(name, on);
+        }
+        if !names.is_empty() {
+            return Ok(String::new());
+        }
+    }
+    if quiet {
+        return Ok(String::new());
+    }
+
+    // with no names every option is listed, `-s`/`-u` alone filter by state
+    let listed: Vec<&str> = if names.is_empty() {
+        SHOPT_OPTIONS
+            .iter()
+            .copied()
+            .filter(|name| set.is_none_or(|on| shopt_enabled(name) == on))
+            .collect()
+    } else {
+        names
+    };
+
+    let lines: Vec<String> = listed
+        .iter()
+        .map(|name| {
+            let on = shopt_enabled(name);
+            if reusable {
+                format!("shopt {} {}", if on { "-s" } else { "-u" }, name)
+            } else {
+                format!("{:<15}\t{}", name, if on { "on" } else { "off" })
+            }
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+pub fn shopt_handler(
+    args: &[String],
+    command: &str,
+    redirect: bool,
+    redirects: Vec<(String, String)>,
+) {
+    builtin_output_handler(shopt_output(args, command), redirect, redirects);
+}
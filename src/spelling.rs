@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// optimal string alignment distance, a swap of two neighbouring characters counts as one edit
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut best = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+// short names tolerate a single typo, longer ones two
+fn max_distance(name: &str) -> usize {
+    if name.chars().count() <= 4 { 1 } else { 2 }
+}
+
+fn closest_directory(parent: &Path, name: &str) -> Option<String> {
+    let search_dir = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+    let mut best: Option<(usize, String)> = None;
+
+    for entry in fs::read_dir(search_dir).ok()?.flatten() {
+        let Ok(candidate) = entry.file_name().into_string() else {
+            continue;
+        };
+        if !entry.path().is_dir() {
+            continue;
+        }
+        let distance = edit_distance(name, &candidate);
+        // ties go to the first name in order, not whichever the directory listed first
+        if distance <= max_distance(name) && best.as_ref().is_none_or(|best| (distance, &candidate) < (best.0, &best.1)) {
+            best = Some((distance, candidate));
+        }
+    }
+    best.map(|(_, candidate)| candidate)
+}
+
+// fixes each missing component of `path` against the directories that do exist
+pub fn correct_directory(path: &str) -> Option<PathBuf> {
+    let mut corrected = PathBuf::new();
+    let mut changed = false;
+
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => {
+                let candidate = corrected.join(name);
+                if candidate.is_dir() {
+                    corrected = candidate;
+                } else {
+                    let fixed = closest_directory(&corrected, name.to_str()?)?;
+                    corrected.push(fixed);
+                    changed = true;
+                }
+            }
+            other => corrected.push(other),
+        }
+    }
+
+    if changed { Some(corrected) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        let cases = [
+            ("", "", 0),
+            ("abc", "abc", 0),
+            ("", "abc", 3),
+            ("docs", "dosc", 1),
+            ("docs", "doc", 1),
+            ("docs", "dogs", 1),
+            ("Documents", "Docuemnts", 1),
+            ("kitten", "sitting", 3),
+            ("ca", "abc", 3),
+            ("été", "ete", 2),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(edit_distance(a, b), expected, "{:?} {:?}", a, b);
+            assert_eq!(edit_distance(b, a), expected, "{:?} {:?}", b, a);
+        }
+    }
+
+    #[test]
+    fn corrects_each_missing_component() {
+        let root = std::env::temp_dir().join(format!("shelly-spelling-{}", std::process::id()));
+        for dir in ["src/bin", "docs", "dogs", "tests"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("notes"), "").unwrap();
+        let at = |path: &str| format!("{}/{}", root.display(), path);
+        let cases = [
+            ("sr/bni", Some("src/bin")),
+            ("src/bin", None),
+            // one typo is all a short name gets, and ties go to the first name
+            ("dox", None),
+            ("dos", Some("docs")),
+            ("tset", None),
+            ("tetss", Some("tests")),
+            ("tsets", Some("tests")),
+            // files are not directories to correct to
+            ("notse", None),
+            ("src/nothing", None),
+        ];
+        let results: Vec<_> = cases.iter().map(|(path, _)| correct_directory(&at(path))).collect();
+        let _ = fs::remove_dir_all(&root);
+        for ((path, expected), result) in cases.iter().zip(results) {
+            assert_eq!(result, expected.map(|fixed| PathBuf::from(at(fixed))), "{:?}", path);
+        }
+    }
+}
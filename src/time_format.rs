@@ -0,0 +1,105 @@
+use std::ffi::CString;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+unsafe extern "C" {
+    // not exported by the libc crate, localtime_r alone may skip reading TZ
+    fn tzset();
+}
+
+pub fn now_epoch() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// strftime(3) in the local time zone, an unusable format gives an empty string
+pub fn format_local_time(format: &str, epoch: i64) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let time = epoch as libc::time_t;
+    // SAFETY: tm is plain data that localtime_r fills in
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the duration of the call
+    let converted = unsafe {
+        tzset();
+        libc::localtime_r(&time, &mut tm)
+    };
+    if converted.is_null() {
+        return String::new();
+    }
+
+    // strftime reports 0 both for "did not fit" and for an empty result, so grow a few times
+    let mut capacity = 128;
+    while capacity <= 4096 {
+        let mut buffer = vec![0u8; capacity];
+        // SAFETY: the buffer length is passed along and tm was filled above
+        let written = unsafe {
+            libc::strftime(
+                buffer.as_mut_ptr() as *mut libc::c_char,
+                buffer.len(),
+                format.as_ptr(),
+                &tm,
+            )
+        };
+        if written > 0 {
+            buffer.truncate(written);
+            return String::from_utf8_lossy(&buffer).into_owned();
+        }
+        capacity *= 4;
+    }
+    String::new()
+}
+
+// a moment given as epoch seconds, an age like `90s`, `15m`, `2h`, `3d` or `1w`,
+// or a local `YYYY-MM-DD[ HH:MM[:SS]]`
+pub fn parse_time(spec: &str, now: i64) -> Option<i64> {
+    let spec = spec.trim();
+    if !spec.is_empty() && spec.chars().all(|c| c.is_ascii_digit()) {
+        return spec.parse().ok();
+    }
+
+    if let Some(unit) = spec.chars().last()
+        && let Ok(amount) = spec[..spec.len() - unit.len_utf8()].parse::<i64>()
+    {
+        let seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        return Some(now - amount * seconds);
+    }
+
+    let (date, time) = match spec.split_once([' ', 'T']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (spec, None),
+    };
+    let date: Vec<i32> = date.split('-').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let time: Vec<i32> = match time {
+        Some(time) => time.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?,
+        None => Vec::new(),
+    };
+    if date.len() != 3 || time.len() > 3 || time.len() == 1 {
+        return None;
+    }
+
+    // SAFETY: tm is plain data, mktime only reads and normalises it
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = date[0] - 1900;
+    tm.tm_mon = date[1] - 1;
+    tm.tm_mday = date[2];
+    tm.tm_hour = time.first().copied().unwrap_or(0);
+    tm.tm_min = time.get(1).copied().unwrap_or(0);
+    tm.tm_sec = time.get(2).copied().unwrap_or(0);
+    tm.tm_isdst = -1;
+    // SAFETY: tm is a valid, initialised struct
+    let epoch = unsafe {
+        tzset();
+        libc::mktime(&mut tm)
+    };
+    if epoch == -1 { None } else { Some(epoch as i64) }
+}
@@ -0,0 +1,70 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+// $XDG_DATA_HOME/shelly/<name>, falling back to ~/.local/share
+pub fn data_file(name: &str) -> Option<PathBuf> {
+    let data_dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(data_dir.join("shelly").join(name))
+}
+
+// $XDG_STATE_HOME/shelly/<name>, falling back to ~/.local/state
+pub fn state_file(name: &str) -> Option<PathBuf> {
+    let state_dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_dir.join("shelly").join(name))
+}
+
+// advisory flock(2) on `<file>.lock`, every session sharing the file takes it around
+// reads and writes. The file itself may be replaced by a rename, so a lock on it would
+// not hold. Released when the returned file is dropped, and skipped if it cannot be made
+pub fn lock_file(path: &Path, exclusive: bool) -> Option<File> {
+    let mut name = path.file_name()?.to_os_string();
+    name.push(".lock");
+    let lock = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path.with_file_name(name))
+        .ok()?;
+    let operation = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+    loop {
+        // SAFETY: the descriptor stays open for as long as `lock` lives
+        if unsafe { libc::flock(lock.as_raw_fd(), operation) } == 0 {
+            return Some(lock);
+        }
+        if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            return None;
+        }
+    }
+}
+
+// writes `contents` to a temp file beside `path` and renames it over `path`, so another
+// session never reads half a file. The caller holds the lock_file lock, the permissions
+// of the file being replaced are kept
+pub fn replace_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let temp_path = path.with_extension(format!("tmp.{}", std::process::id()));
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&temp_path)
+        .and_then(|mut file| {
+            if let Ok(metadata) = fs::metadata(path) {
+                let _ = file.set_permissions(metadata.permissions());
+            }
+            file.write_all(contents)
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}